@@ -1,32 +1,37 @@
-use std::{
-    collections::VecDeque,
-    fs::{self, DirEntry},
-    io,
-    path::{Path, PathBuf},
-};
+use std::{collections::VecDeque, io, path::Path};
 
-pub fn resolve_relative_path(path: PathBuf, truncate: bool) -> io::Result<VecDeque<String>> {
-    let mut l: VecDeque<String> = VecDeque::new();
-    let mut path = path;
+/// Path segments of `path` relative to the routing directory `root`
+pub fn resolve_relative_path(root: &Path, path: &Path, truncate: bool) -> io::Result<VecDeque<String>> {
+    let relative = path
+        .strip_prefix(root)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let path = if truncate {
+        relative.with_extension("")
+    } else {
+        relative.to_path_buf()
+    };
 
-    while !path.ends_with("routing") {
-        if let Some(a) = path.file_name().map(|p| p.to_str().unwrap()) {
-            let file = if truncate {
-                if let Some(e) = path.extension() {
-                    let extension = format!(".{}", e.to_str().unwrap());
-                    a.to_string().replace(&extension, "")
-                } else {
-                    a.to_string()
-                }
-            } else {
-                a.to_string()
-            };
+    Ok(path
+        .iter()
+        .map(|p| p.to_str().unwrap().to_string())
+        .collect())
+}
 
-            l.push_front(file);
-        }
+/// Collects every directory and file beneath `dir`, sorted by path.
+#[cfg(feature = "nightly")]
+pub fn visit_dirs(dir: &Path, l: &mut Vec<std::path::PathBuf>) -> io::Result<()> {
+    if dir.is_dir() {
+        let mut entries = std::fs::read_dir(dir)?.collect::<io::Result<Vec<std::fs::DirEntry>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
 
-        path.pop();
-    }
+        for entry in entries {
+            let path = entry.path();
+            l.push(path.clone());
 
-    Ok(l)
+            if path.is_dir() {
+                visit_dirs(&path, l)?;
+            }
+        }
+    }
+    Ok(())
 }
@@ -1,65 +1,303 @@
+#![cfg_attr(feature = "nightly", feature(proc_macro_tracked_path))]
+
+use std::path::PathBuf;
+
 use proc_macro::TokenStream;
-use routing::construct_routing_system;
-use syn::{parse_macro_input, DataStruct, DeriveInput};
-use syn::Data::Struct;
+use routing::{construct_routing_system, RoutingConfig};
+use syn::{parse::Parser, parse_macro_input, parse_quote, punctuated::Punctuated, DataEnum, DataStruct, DataUnion, DeriveInput, Expr, ExprLit, Lit, MetaNameValue, Token};
+use syn::Data::{Enum, Struct, Union};
 
 
 mod file_system;
 mod routing;
 
+/**
+ * Generates the router from a routing directory
+ *
+ * Accepts the optional arguments
+ * - `dir`: routing directory, relative paths are resolved against `CARGO_MANIFEST_DIR`.
+ *   Falls back to `POTION_ROUTING_DIR` in `.env`. A missing directory is a compile error
+ * - `fn_name`: name of the generated router function, `router` by default
+ * - `mount`: base path the routes are served under
+ * - `module`: name of the generated module tree, `routing` by default. `ROUTES` and `routes`
+ *   are prefixed with it when set, e.g. `ADMIN_ROUTES` and `admin_routes`
+ *
+ * Invoking the macro several times requires distinct `module` and `fn_name` arguments
+ *
+ * The generated router matches the whole path of each route file, up to `warp::path::end()`,
+ * before calling its `initialize`. `initialize` must not match its own path again, and
+ * sub-paths are served by their own files or a catch-all `[...name].rs` rather than by
+ * `initialize`
+ *
+ * ```ignore
+ *     potion::routing!(dir = "src/admin/routing", module = admin, fn_name = admin_router, mount = "admin");
+ * ```
+ */
 #[proc_macro]
-pub fn routing(_p: TokenStream) -> TokenStream {
-    let potion_routing_dir = dotenv::dotenv_iter()
-        .map(|env| {
-            env.into_iter().flatten().find_map(|(k, v)| {
-                if k == "POTION_ROUTING_DIR" {
-                    Some(v)
-                } else {
-                    None
-                }
+pub fn routing(p: TokenStream) -> TokenStream {
+    let args = match Punctuated::<MetaNameValue, Token![,]>::parse_terminated.parse(p) {
+        Ok(args) => args,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    let mut dir = None;
+    let mut fn_name: Option<String> = None;
+    let mut mount: Option<String> = None;
+    let mut module: Option<String> = None;
+
+    for arg in args {
+        let value = match &arg.value {
+            Expr::Lit(ExprLit { lit: Lit::Str(s), .. }) => s.value(),
+            Expr::Path(path) if path.path.get_ident().is_some() => {
+                path.path.get_ident().unwrap().to_string()
+            }
+            _ => {
+                return syn::Error::new_spanned(&arg.value, "Expected a string literal or identifier")
+                    .to_compile_error()
+                    .into()
+            }
+        };
+
+        match arg.path.get_ident().map(|i| i.to_string()).as_deref() {
+            Some("dir") => dir = Some((value, syn::spanned::Spanned::span(&arg.value))),
+            Some("fn_name") => fn_name = Some(value),
+            Some("mount") => mount = Some(value),
+            Some("module") => module = Some(value),
+            _ => {
+                return syn::Error::new_spanned(&arg.path, "Unknown argument, expected `dir`, `fn_name`, `mount` or `module`")
+                    .to_compile_error()
+                    .into()
+            }
+        }
+    }
+
+    let potion_routing_dir = dir.or_else(|| {
+        dotenv::dotenv_iter()
+            .map(|env| {
+                env.into_iter().flatten().find_map(|(k, v)| {
+                    if k == "POTION_ROUTING_DIR" {
+                        Some((v, proc_macro::Span::call_site().into()))
+                    } else {
+                        None
+                    }
+                })
             })
-        })
-        .unwrap_or(None);
+            .unwrap_or(None)
+    });
+
+    let Some((potion_routing_dir, span)) = potion_routing_dir else {
+        return syn::Error::new(
+            proc_macro::Span::call_site().into(),
+            "'POTION_ROUTING_DIR' is unset. Either pass `dir = \"...\"` or make sure you have \".env\" file correctly configured",
+        )
+        .to_compile_error()
+        .into();
+    };
 
-    if potion_routing_dir.is_none() {
-        panic!("'POTION_ROUTING_DIR' is unset. Are you sure you have \".env\" file correctly configured?")
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").map(PathBuf::from).unwrap_or_default();
+    let dir = manifest_dir.join(&potion_routing_dir);
+    let dir = dir.canonicalize().unwrap_or(dir);
+
+    if !dir.is_dir() {
+        return syn::Error::new(
+            span,
+            format!("Routing directory `{}` doesn't exist ({})", potion_routing_dir, dir.display()),
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let mut config = RoutingConfig::new(dir);
+
+    if let Some(fn_name) = fn_name {
+        config.fn_name = fn_name;
     }
 
-    let ts = construct_routing_system(&potion_routing_dir.unwrap())
-        .expect("Failed to construct module tree");
+    if let Some(module) = module {
+        config.module = module;
+    }
+
+    if let Some(mount) = mount {
+        config.mount = mount
+            .split("/")
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .collect();
+    }
+
+    let ts = match construct_routing_system(&config) {
+        Ok(ts) => ts,
+        Err(e) => {
+            return syn::Error::new(span, format!("Failed to construct module tree: {}", e))
+                .to_compile_error()
+                .into()
+        }
+    };
 
     ts.parse().unwrap()
 }
 
-#[proc_macro_derive(IntoContext)]
-pub fn hello_derive(item: TokenStream) -> TokenStream {
+/// Implements `potion::Context` for a struct. Generic structs are supported as long as the
+/// struct is `Clone + Send + Sync + 'static`
+///
+/// Fields annotated with `#[potion(extract)]` get a `<field>_filter(&router)` function returning
+/// a filter that yields a clone of the field per request. The `potion::Extensions` field
+/// annotated with `#[potion(extensions)]` is copied into each `potion::Router`
+///
+/// ```ignore
+///     #[derive(Clone, potion::IntoContext)]
+///     pub struct RouterContext {
+///         #[potion(extract)]
+///         pub db: Pool<Postgres>,
+///         #[potion(extensions)]
+///         pub extensions: potion::Extensions,
+///     }
+///
+///     warp::get().and(RouterContext::db_filter(&router)).then(|db: Pool<Postgres>| async move { ... })
+/// ```
+#[proc_macro_derive(IntoContext, attributes(potion))]
+pub fn into_context_derive(item: TokenStream) -> TokenStream {
     let ast = parse_macro_input!(item as DeriveInput);
-    let name = ast.ident;
 
-    match ast.data {
-        Struct(DataStruct { .. }) => {},
-        _ => unimplemented!("Only works for structs"),
+    let fields = match &ast.data {
+        Struct(DataStruct { fields, .. }) => fields,
+        Enum(DataEnum { enum_token, .. }) => {
+            return syn::Error::new_spanned(enum_token, "`IntoContext` can only be derived for structs")
+                .to_compile_error()
+                .into()
+        }
+        Union(DataUnion { union_token, .. }) => {
+            return syn::Error::new_spanned(union_token, "`IntoContext` can only be derived for structs")
+                .to_compile_error()
+                .into()
+        }
     };
 
+    let name = &ast.ident;
+    let mut extractors = vec![];
+    let mut extensions = None;
+    for field in fields {
+        let attributes = match resolve_field_attributes(field) {
+            Ok(attributes) => attributes,
+            Err(e) => return e.to_compile_error().into(),
+        };
+
+        if !attributes.extract && !attributes.extensions {
+            continue;
+        }
+
+        let Some(ident) = &field.ident else {
+            return syn::Error::new_spanned(field, "`#[potion(...)]` requires a named field")
+                .to_compile_error()
+                .into();
+        };
+
+        if attributes.extensions {
+            if extensions.is_some() {
+                return syn::Error::new_spanned(
+                    field,
+                    "Only one field can be marked `#[potion(extensions)]`",
+                )
+                .to_compile_error()
+                .into();
+            }
+
+            extensions = Some(quote::quote! {
+                fn extensions(&self) -> ::core::option::Option<&::potion::Extensions> {
+                    ::core::option::Option::Some(&self.#ident)
+                }
+            });
+        }
+
+        if !attributes.extract {
+            continue;
+        }
+
+        let ty = &field.ty;
+        let filter_name = quote::format_ident!("{}_filter", ident.to_string().trim_start_matches("r#"));
+        let doc = format!("Filter yielding a clone of `{}` per request", ident);
+
+        extractors.push(quote::quote! {
+            #[doc = #doc]
+            pub fn #filter_name(
+                router: &::potion::Router,
+            ) -> impl ::warp::Filter<Extract = (#ty,), Error = ::std::convert::Infallible> + ::core::clone::Clone
+            where
+                #ty: ::core::clone::Clone + ::core::marker::Send + ::core::marker::Sync + 'static,
+            {
+                let value = router
+                    .try_downcast::<Self>()
+                    .expect(::core::concat!("Router context is not `", ::core::stringify!(#name), "`"))
+                    .#ident
+                    .clone();
+
+                ::warp::Filter::map(::warp::any(), move || ::core::clone::Clone::clone(&value))
+            }
+        });
+    }
+
+    let mut generics = ast.generics.clone();
+    generics.make_where_clause().predicates.push(parse_quote!(
+        Self: ::core::clone::Clone + ::core::marker::Send + ::core::marker::Sync + 'static
+    ));
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
     quote::quote!{
-        impl potion::Context for #name {
-            fn as_any(&self) -> &dyn Any {
+        impl #impl_generics ::potion::Context for #name #ty_generics #where_clause {
+            fn as_any(&self) -> &dyn ::core::any::Any {
                 self
             }
-        
-            fn as_any_mut(&mut self) -> &mut dyn Any {
+
+            fn as_any_mut(&mut self) -> &mut dyn ::core::any::Any {
                 self
             }
-        
-            fn box_clone(&self) -> Box<dyn potion::Context + Send + Sync> {
-                Box::new((*self).clone())
+
+            fn box_clone(&self) -> ::std::boxed::Box<dyn ::potion::Context + ::core::marker::Send + ::core::marker::Sync> {
+                ::std::boxed::Box::new(::core::clone::Clone::clone(self))
             }
+
+            #extensions
+        }
+
+        impl #impl_generics #name #ty_generics #where_clause {
+            #(#extractors)*
         }
     }.into()
 }
 
+/// Arguments of the `#[potion(...)]` attributes of a field
+#[derive(Default)]
+struct FieldAttributes {
+    /// `#[potion(extract)]`
+    extract: bool,
+    /// `#[potion(extensions)]`, the `potion::Extensions` returned by `Context::extensions`
+    extensions: bool,
+}
+
+fn resolve_field_attributes(field: &syn::Field) -> syn::Result<FieldAttributes> {
+    let mut attributes = FieldAttributes::default();
+
+    for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("potion")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("extract") {
+                attributes.extract = true;
+                return Ok(());
+            }
+
+            if meta.path.is_ident("extensions") {
+                attributes.extensions = true;
+                return Ok(());
+            }
+
+            Err(meta.error("Unknown argument, expected `extract` or `extensions`"))
+        })?;
+    }
+
+    Ok(attributes)
+}
+
 #[test]
 fn test_router() {
-    let a = construct_routing_system("D:\\potion-test\\src\\routing");
+    let a = construct_routing_system(&RoutingConfig::new(PathBuf::from("D:\\potion-test\\src\\routing")));
     dbg!(a);
 }
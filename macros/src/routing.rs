@@ -1,38 +1,117 @@
-use std::{collections::VecDeque, fs, io, path::Path};
+use std::{
+    collections::VecDeque,
+    fs, io,
+    path::{Path, PathBuf},
+};
 
 use crate::file_system::resolve_relative_path;
+#[cfg(feature = "nightly")]
+use crate::file_system::visit_dirs;
 
-pub fn construct_routing_system(path: &str) -> io::Result<String> {
-    let mut ts = String::from("mod routing { ");
+/// Arguments of `routing!()`
+pub struct RoutingConfig {
+    /// Absolute path of the routing directory
+    pub dir: PathBuf,
+    /// Name of the generated router function
+    pub fn_name: String,
+    /// Path segments the routes are served under
+    pub mount: Vec<String>,
+    /// Name of the generated module tree
+    pub module: String,
+}
+
+impl RoutingConfig {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            fn_name: String::from("router"),
+            mount: vec![],
+            module: String::from("routing"),
+        }
+    }
+
+    /// `ROUTES`, prefixed by the module name when it isn't `routing`, e.g. `ADMIN_ROUTES`
+    pub fn manifest_name(&self) -> String {
+        match self.module.as_str() {
+            "routing" => String::from("ROUTES"),
+            module => format!("{}_ROUTES", module.to_uppercase()),
+        }
+    }
+
+    /// `routes`, prefixed by the module name when it isn't `routing`, e.g. `admin_routes`
+    pub fn reverse_module_name(&self) -> String {
+        match self.module.as_str() {
+            "routing" => String::from("routes"),
+            module => format!("{}_routes", module),
+        }
+    }
+}
+
+pub fn construct_routing_system(config: &RoutingConfig) -> io::Result<String> {
+    let mut ts = format!("mod {} {{ ", config.module);
     let mut l: Vec<VecDeque<String>> = vec![];
-    construct_import_tree(path, &mut ts, &mut l)?;
+    construct_import_tree(&config.dir, &config.dir, &mut ts, &mut l)?;
     ts += "}";
 
     ts += "\n";
-    ts += &construct_router_tree(&mut l)?;
+    ts += &construct_router_tree(config, &mut l)?;
+
+    ts += "\n";
+    ts += &construct_route_manifest(config, &l);
+
+    ts += "\n";
+    ts += &construct_reverse_routes(config, &l);
+
+    #[cfg(feature = "nightly")]
+    track_dependencies(&config.dir)?;
 
     Ok(ts)
 }
 
+/// Registers the routing directory and its files as dependencies of the invoking crate, so the
+/// router is regenerated when routes are added, changed or removed. Proc macros can't register
+/// dependencies on stable, there a build script has to
+/// `println!("cargo:rerun-if-changed=src/routing")`.
+#[cfg(feature = "nightly")]
+pub fn track_dependencies(path: &Path) -> io::Result<()> {
+    let mut l: Vec<PathBuf> = vec![];
+    visit_dirs(path, &mut l)?;
+
+    for path in l.iter().map(fs::canonicalize) {
+        proc_macro::tracked::path(path?.to_str().unwrap());
+    }
+
+    proc_macro::tracked::path(fs::canonicalize(path)?.to_str().unwrap());
+    Ok(())
+}
+
+/// Files are imported with an absolute `#[path]`, so the module tree doesn't depend on the
+/// location of the routing directory or of the file invoking `routing!()`.
 pub fn construct_import_tree(
-    dir: &str,
+    root: &Path,
+    dir: &Path,
     s: &mut String,
     l: &mut Vec<VecDeque<String>>,
 ) -> io::Result<()> {
-    if Path::new(dir).is_dir() {
-        for entry in fs::read_dir(dir)? {
-            let entry = entry?;
+    if dir.is_dir() {
+        // Sorted so the generated module tree doesn't depend on the order returned by the file system
+        let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
             let path = entry.path();
 
             if path.is_dir() {
-                *s += &format!("pub mod {} {{", entry.file_name().to_str().unwrap());
-                construct_import_tree(&path.to_str().unwrap().to_string(), s, l)?;
+                let name = entry.file_name().to_str().unwrap().to_string();
+                *s += &format!("pub mod {} {{", resolve_module_name(&name));
+                construct_import_tree(root, &path, s, l)?;
                 *s += "} ";
             } else {
-                if path.extension().unwrap() == "rs" {
-                    let file = entry.file_name().to_str().unwrap().replace(".rs", "");
-                    *s += &format!(" pub mod {}; ", &file);
-                    l.push(resolve_relative_path(path, true)?);
+                if path.extension().is_some_and(|e| e == "rs") {
+                    let name = entry.file_name().to_str().unwrap().to_string();
+                    let file = name.replace(".rs", "");
+                    *s += &format!("#[path = {:?}] pub mod {}; ", path.to_str().unwrap(), resolve_module_name(&file));
+                    l.push(resolve_relative_path(root, &path, true)?);
                 }
             }
         }
@@ -40,53 +119,568 @@ pub fn construct_import_tree(
     Ok(())
 }
 
-pub fn construct_router_tree(l: &mut Vec<VecDeque<String>>) -> io::Result<String> {
-    let mut ts = String::from("fn router(context: Box<dyn potion::Context + Send + Sync>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone { ");
+pub fn construct_router_tree(config: &RoutingConfig, l: &mut Vec<VecDeque<String>>) -> io::Result<String> {
+    let mut ts = format!("fn {}(context: Box<dyn potion::Context + Send + Sync>) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {{ ", config.fn_name);
+    let mount: Vec<&String> = config.mount.iter().collect();
+
+    let mut tree_list: Vec<&VecDeque<String>> = l.iter().filter(|tree| is_route(tree)).collect();
+    tree_list.sort_by_cached_key(|tree| resolve_specificity(tree));
+
+    for error in resolve_conflicts(&tree_list) {
+        ts = format!("compile_error!({:?}); {}", error, ts);
+    }
+
+    if tree_list.is_empty() {
+        ts += "warp::any().and_then(|| async { Err::<&'static str, warp::Rejection>(warp::reject::not_found()) }) }";
+        return Ok(ts);
+    }
 
-    let mut tree_list: Vec<&VecDeque<String>> = l.iter().filter(|tree| tree.iter().last().unwrap() == "index").collect();
+    ts += &construct_path_filter(&mount);
 
-    if let Some(tree) = tree_list.pop() {
+    let middleware_list: Vec<&VecDeque<String>> = l
+        .iter()
+        .filter(|tree| tree.iter().last().is_some_and(|r| r == "_middleware"))
+        .collect();
+
+    for (i, tree) in tree_list.iter().enumerate() {
         if tree.len() <= 0 {
             panic!("Failed to construct router tree. Tried to link file with invalid path")
         }
-        if tree.iter().last().unwrap() == "index" {
-            for (i, route) in tree.iter().enumerate() {
-                if i == 0 {
-                    ts += &format!("warp::path(\"{}\").and(", route)
-                } else if i == tree.len() - 1 {
-                    ts += &format!("routing::{}::initialize( potion::Router::new(context.clone(), &[{}]))", resolve_import_path(tree.clone()), tree.iter().map(|r| format!("\"{r}\"")).collect::<Vec<String>>().join(", "));
-                } else {
-                    ts += &format!("warp::path(\"{}\").and(", route)
-                }
-            }
 
-            ts += &")".repeat(tree.len() - 1);
+        if i == 0 {
+            ts += &construct_route(tree, &tree_list, &middleware_list, config);
+        } else {
+            ts += &format!(".or({})", construct_route(tree, &tree_list, &middleware_list, config));
+        }
+
+        // Method files of a path are adjacent, the fallback is linked after the last one so it
+        // comes before any catch-all which would also match the path
+        let segments = resolve_route_segments(tree);
+        if resolve_method(tree).is_some()
+            && tree_list
+                .get(i + 1)
+                .is_none_or(|next| resolve_method(next).is_none() || resolve_route_segments(next) != segments)
+        {
+            ts += &format!(".or({})", construct_method_fallback(tree, &tree_list, &middleware_list, config));
         }
-    } else {
-        return Ok(ts);
     }
 
+    ts += &")".repeat(mount.len());
+    ts += " }";
+
+    Ok(ts)
+}
+
+/// Generates the filter for a single route, matched by the path filter of
+/// `construct_route_path`. `get.rs` also serves `HEAD` unless the path has a `head.rs`.
+fn construct_route(
+    tree: &VecDeque<String>,
+    tree_list: &[&VecDeque<String>],
+    middleware_list: &[&VecDeque<String>],
+    config: &RoutingConfig,
+) -> String {
+    let mut ts = String::from("{ ");
+    let (path, parentheses) = construct_route_path(tree, config);
+    ts += &path;
+
+    match resolve_method(tree) {
+        Some("GET") if !resolve_path_methods(tree, tree_list).contains(&"HEAD") => {
+            ts += "warp::get().or(warp::head()).unify().and("
+        }
+        Some(method) => ts += &format!("warp::{}().and(", method.to_lowercase()),
+        None => {}
+    }
+
+    ts += &construct_middleware(
+        tree,
+        middleware_list,
+        config,
+        format!("{}::{}::initialize(router.clone())", config.module, resolve_import_path(tree.clone())),
+    );
+
+    ts += &")".repeat(parentheses + resolve_method(tree).iter().len());
+    ts += " }";
+    ts
+}
+
+/// Wraps the filter of a route with the `_middleware.rs` files above it, once the path has
+/// matched, the middleware closest to the root being the outermost.
+fn construct_middleware(
+    tree: &VecDeque<String>,
+    middleware_list: &[&VecDeque<String>],
+    config: &RoutingConfig,
+    filter: String,
+) -> String {
+    let mut middleware: Vec<&&VecDeque<String>> = middleware_list
+        .iter()
+        .filter(|m| m.len() <= tree.len() && m.iter().zip(tree.iter()).take(m.len() - 1).all(|(a, b)| a == b))
+        .collect();
+    middleware.sort_by_key(|m| m.len());
+
+    let mut ts = String::new();
+
+    for m in &middleware {
+        ts += &format!("{}::{}::middleware(router.clone(), ", config.module, resolve_import_path((**m).clone()));
+    }
+
+    ts += &filter;
+
+    if !middleware.is_empty() {
+        ts += ".map(warp::Reply::into_response).boxed()";
+    }
+
+    ts += &")".repeat(middleware.len());
+    ts
+}
+
+/// Declares the `router` of the route and opens the `.and(` chain matching its path, to be
+/// closed by the caller with the returned number of parentheses. Static segments before the
+/// first dynamic one are matched with `warp::path`, the rest of the path is checked by
+/// `potion::Router::match_path` without consuming it, so `potion::Router::with_context` can
+/// extract the values of the dynamic segments.
+fn construct_route_path(tree: &VecDeque<String>, config: &RoutingConfig) -> (String, usize) {
+    let segments = resolve_route_segments(tree);
+    let prefix = segments
+        .iter()
+        .position(|s| !matches!(resolve_segment(s), Segment::Static(_)))
+        .unwrap_or(segments.len());
+
+    let mut ts = format!(
+        "let router = potion::Router::new(context.clone(), &[{}]){}; ",
+        tree.iter().map(|r| format!("\"{r}\"")).collect::<Vec<String>>().join(", "),
+        match config.mount.is_empty() {
+            true => String::new(),
+            false => format!(
+                ".with_mount(&[{}])",
+                config.mount.iter().map(|r| format!("\"{r}\"")).collect::<Vec<String>>().join(", ")
+            ),
+        }
+    );
+
+    ts += &construct_path_filter(&segments[..prefix]);
+    ts += match prefix == segments.len() {
+        true => "warp::path::end().and(",
+        false => "router.match_path().and(",
+    };
+
+    (ts, prefix + 1)
+}
+
+/// Generates `ROUTES` listing every linked route in the order they are matched.
+pub fn construct_route_manifest(config: &RoutingConfig, l: &[VecDeque<String>]) -> String {
+    let mut tree_list: Vec<&VecDeque<String>> = l.iter().filter(|tree| is_route(tree)).collect();
+    tree_list.sort_by_cached_key(|tree| resolve_specificity(tree));
+
+    let routes = tree_list
+        .iter()
+        .map(|tree| {
+            let segments = resolve_route_segments(tree);
+            let file = config.dir.join(resolve_file_path(tree));
+            let template = match segments.is_empty() {
+                true => String::from("routing::index"),
+                false => format!(
+                    "routing::{}::index",
+                    segments.iter().map(|s| s.as_str()).collect::<Vec<&str>>().join("::")
+                ),
+            };
+
+            format!(
+                "potion::RouteInfo {{ path: {:?}, method: {}, file: {:?}, module: {:?}, template: {:?}, params: &[{}] }}",
+                format!(
+                    "/{}",
+                    config.mount.iter().chain(segments.iter().copied()).map(|s| s.as_str()).collect::<Vec<&str>>().join("/")
+                ),
+                match resolve_method(tree) {
+                    Some(method) => format!("Some({method:?})"),
+                    None => String::from("None"),
+                },
+                file.to_str().unwrap(),
+                format!("{}::{}", config.module, resolve_import_path((*tree).clone())),
+                template,
+                segments
+                    .iter()
+                    .filter_map(|s| resolve_segment(s).param_name().map(|p| format!("{p:?}")))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    format!("pub const {}: &[potion::RouteInfo] = &[{routes}];", config.manifest_name())
+}
+
+/// Generates the `routes` module with a function per path returning a `potion::uri::Builder`,
+/// e.g. `routes::users_id(42)` for `users/[id]/index.rs`. Catch-all segments are prefixed with
+/// `all_` and optional catch-all segments with `opt_`, e.g. `routes::docs_opt_rest(&["intro"])`.
+pub fn construct_reverse_routes(config: &RoutingConfig, l: &[VecDeque<String>]) -> String {
+    let mut tree_list: Vec<&VecDeque<String>> = l.iter().filter(|tree| is_route(tree)).collect();
+    tree_list.sort_by_cached_key(|tree| resolve_specificity(tree));
+
+    let mut ts = format!("pub mod {} {{ ", config.reverse_module_name());
+    let mut functions: Vec<(String, Vec<&String>, &VecDeque<String>)> = vec![];
+
     for tree in tree_list {
-        if tree.iter().last().unwrap() == "index" {
-            for (i, route) in tree.iter().enumerate() {
-                if i == 0 {
-                    ts += &format!(".or(warp::path(\"{}\").and(", route)
-                } else if i == tree.len() - 1 {
-                    ts += &format!("routing::{}::initialize( potion::Router::new(context.clone(), &[{}]))", resolve_import_path(tree.clone()), tree.iter().map(|r| format!("\"{r}\"")).collect::<Vec<String>>().join(", "));
-                } else {
-                    ts += &format!("warp::path(\"{}\").and(", route)
+        let segments = resolve_route_segments(tree);
+
+        if functions.iter().any(|(_, other, _)| *other == segments) {
+            continue;
+        }
+
+        let name = match segments.is_empty() {
+            true => String::from("index"),
+            false => segments
+                .iter()
+                .map(|s| match resolve_segment(s) {
+                    Segment::Static(name) => name.to_string(),
+                    Segment::Param(param) => param.to_string(),
+                    Segment::CatchAll(param) => format!("all_{param}"),
+                    Segment::OptionalCatchAll(param) => format!("opt_{param}"),
+                })
+                .collect::<Vec<String>>()
+                .join("_")
+                .replace(|c: char| !c.is_ascii_alphanumeric(), "_"),
+        };
+
+        if let Some((_, _, other)) = functions.iter().find(|(other, _, _)| *other == name) {
+            ts = format!(
+                "compile_error!({:?}); {}",
+                format!(
+                    "Conflicting routes: `{}` and `{}` both generate `{}::{}`",
+                    resolve_file_path(other),
+                    resolve_file_path(tree),
+                    config.reverse_module_name(),
+                    name
+                ),
+                ts
+            );
+            continue;
+        }
+
+        let mut args = vec![];
+        let mut body = String::from("potion::uri::Builder::new(\"/\")");
+
+        for segment in &config.mount {
+            body += &format!(".path_add({segment:?})");
+        }
+
+        for segment in &segments {
+            match resolve_segment(segment) {
+                Segment::Static(name) => body += &format!(".path_add({name:?})"),
+                Segment::Param(param) => {
+                    args.push(format!("{}: impl std::fmt::Display", resolve_param_ident(param)));
+                    body += &format!(".path_add(&{}.to_string())", resolve_param_ident(param));
+                }
+                Segment::CatchAll(param) | Segment::OptionalCatchAll(param) => {
+                    args.push(format!("{}: &[&str]", resolve_param_ident(param)));
+                    body += &format!(".path_extend({})", resolve_param_ident(param));
                 }
             }
-
-            ts += &")".repeat(tree.len());
         }
+
+        ts += &format!(
+            "pub fn {}({}) -> potion::uri::Builder {{ {} }} ",
+            resolve_ident(&name),
+            args.join(", "),
+            body
+        );
+        functions.push((name, segments, tree));
+    }
+
+    ts += "}";
+    ts
+}
+
+/// Argument name of a dynamic segment, e.g. `user_id` for `[user-id]`.
+fn resolve_param_ident(param: &str) -> String {
+    resolve_ident(&param.replace(|c: char| !c.is_ascii_alphanumeric() && c != '_', "_"))
+}
+
+/// Escapes names which are reserved keywords.
+fn resolve_ident(name: &str) -> String {
+    const KEYWORDS: &[&str] = &[
+        "as", "async", "await", "break", "const", "continue", "dyn", "else", "enum", "extern",
+        "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut",
+        "pub", "ref", "return", "static", "struct", "trait", "true", "type", "unsafe", "use",
+        "where", "while", "abstract", "become", "box", "do", "final", "macro", "override", "priv",
+        "typeof", "unsized", "virtual", "yield", "try", "gen",
+    ];
+
+    match name {
+        "self" | "super" | "crate" | "Self" | "_" => format!("{name}_"),
+        _ if name.starts_with(|c: char| c.is_ascii_digit()) => format!("_{name}"),
+        _ if KEYWORDS.contains(&name) => format!("r#{name}"),
+        _ => name.to_string(),
+    }
+}
+
+/// Opens a `.and(` chain matching the given static segments, to be closed by the caller.
+fn construct_path_filter(segments: &[&String]) -> String {
+    segments
+        .iter()
+        .map(|segment| format!("warp::path({:?}).and(", segment))
+        .collect()
+}
+
+/// Generates the `405 Method Not Allowed` fallback of a path served by method files (`get.rs`,
+/// `post.rs`, ...), wrapped with the same middleware as its routes so the `Allow` header isn't
+/// revealed to requests the middleware rejects.
+fn construct_method_fallback(
+    tree: &VecDeque<String>,
+    tree_list: &[&VecDeque<String>],
+    middleware_list: &[&VecDeque<String>],
+    config: &RoutingConfig,
+) -> String {
+    let mut methods = resolve_path_methods(tree, tree_list);
+
+    if methods.contains(&"GET") && !methods.contains(&"HEAD") {
+        methods.push("HEAD");
+        methods.sort();
     }
 
+    let (path, parentheses) = construct_route_path(tree, config);
+    let mut ts = format!("{{ {}", path);
+
+    ts += &construct_middleware(
+        tree,
+        middleware_list,
+        config,
+        format!(
+            "potion::routing::method_not_allowed(&[{}])",
+            methods.iter().map(|m| format!("\"{m}\"")).collect::<Vec<String>>().join(", ")
+        ),
+    );
+    ts += &")".repeat(parentheses);
     ts += " }";
+    ts
+}
 
-    Ok(ts)
+/// Methods served by the method files of the route's path.
+fn resolve_path_methods(tree: &VecDeque<String>, tree_list: &[&VecDeque<String>]) -> Vec<&'static str> {
+    let segments = resolve_route_segments(tree);
+
+    tree_list
+        .iter()
+        .filter(|other| resolve_route_segments(other) == segments)
+        .filter_map(|other| resolve_method(other))
+        .collect()
+}
+
+/// Sort key linking the most specific routes first. Static segments take precedence over
+/// dynamic ones, deeper routes over their parents and catch-all segments come last, so a
+/// catch-all never shadows a more specific sibling. Ties are broken by name so the `.or(...)`
+/// chain is identical on every machine.
+fn resolve_specificity(tree: &VecDeque<String>) -> Vec<(u8, String)> {
+    let mut key: Vec<(u8, String)> = resolve_route_segments(tree)
+        .iter()
+        .map(|r| match resolve_segment(r) {
+            Segment::Static(name) => (0, name.to_string()),
+            Segment::Param(_) => (1, String::new()),
+            Segment::CatchAll(_) => (3, String::new()),
+            Segment::OptionalCatchAll(_) => (4, String::new()),
+        })
+        .collect();
+
+    key.push((2, resolve_method(tree).unwrap_or_default().to_string()));
+    key
+}
+
+/// Detects routes which map to the same url. `index` segments are collapsed the same way as
+/// in `potion::storage::Storage`, parameter names are ignored and an optional catch-all also
+/// matches its parent path. `index.rs` serves every method, so it conflicts with method files
+/// of the same path.
+fn resolve_conflicts(tree_list: &[&VecDeque<String>]) -> Vec<String> {
+    let mut errors = vec![];
+    let mut patterns: Vec<(String, Option<&str>, &VecDeque<String>)> = vec![];
+
+    for tree in tree_list {
+        let method = resolve_method(tree);
+        let segments: Vec<Segment> = resolve_route_segments(tree)
+            .iter()
+            .map(|r| resolve_segment(r))
+            .collect();
+
+        if let Some(i) = segments.iter().position(|s| s.is_catch_all()) {
+            if i != segments.len() - 1 {
+                errors.push(format!(
+                    "Invalid route `{}`: a catch-all segment must be the last segment of the route",
+                    resolve_file_path(tree)
+                ));
+            }
+        }
+
+        let pattern = |segments: &[Segment]| {
+            let pattern = segments
+                .iter()
+                .map(|s| match s {
+                    Segment::Static(name) => name.to_string(),
+                    Segment::Param(_) => String::from("[_]"),
+                    Segment::CatchAll(_) | Segment::OptionalCatchAll(_) => String::from("[..._]"),
+                })
+                .collect::<Vec<String>>()
+                .join("/");
+
+            format!("/{pattern}")
+        };
+
+        let mut tree_patterns = vec![pattern(&segments)];
+
+        if let Some(Segment::OptionalCatchAll(_)) = segments.last() {
+            tree_patterns.push(pattern(&segments[..segments.len() - 1]));
+        }
+
+        for p in tree_patterns {
+            match patterns.iter().find(|(other, other_method, _)| {
+                *other == p && (method.is_none() || other_method.is_none() || *other_method == method)
+            }) {
+                Some((_, _, other)) => errors.push(format!(
+                    "Conflicting routes: `{}` and `{}` both map to `{}`",
+                    resolve_file_path(other),
+                    resolve_file_path(tree),
+                    p
+                )),
+                None => patterns.push((p, method, tree)),
+            }
+        }
+    }
+
+    errors
+}
+
+fn resolve_file_path(tree: &VecDeque<String>) -> String {
+    format!("{}.rs", Vec::from(tree.clone()).join("/"))
+}
+
+/// `index.rs` files, method files such as `get.rs` and dynamic `[name].rs` / `[...name].rs`
+/// files are linked as routes.
+fn is_route(tree: &VecDeque<String>) -> bool {
+    match tree.iter().last() {
+        Some(last) => {
+            last == "index"
+                || resolve_method(tree).is_some()
+                || !matches!(resolve_segment(last), Segment::Static(_))
+        }
+        None => false,
+    }
+}
+
+pub const METHODS: &[&str] = &["GET", "POST", "PUT", "PATCH", "DELETE", "HEAD", "OPTIONS"];
+
+/// Returns the http method of a method file, e.g. `GET` for `users/get.rs`.
+fn resolve_method(tree: &VecDeque<String>) -> Option<&'static str> {
+    let last = tree.iter().last()?;
+    METHODS.iter().find(|m| m.to_lowercase() == *last).copied()
+}
+
+/// Segments of the url the route is served at, without `index` and the method file.
+fn resolve_route_segments(tree: &VecDeque<String>) -> Vec<&String> {
+    let len = tree.len() - resolve_method(tree).iter().len();
+    tree.iter().take(len).filter(|r| *r != "index").collect()
+}
+
+pub enum Segment<'a> {
+    Static(&'a str),
+    /// `[name]`
+    Param(&'a str),
+    /// `[...name]`, matches one or more segments
+    CatchAll(&'a str),
+    /// `[[...name]]`, matches zero or more segments
+    OptionalCatchAll(&'a str),
+}
+
+impl Segment<'_> {
+    pub fn is_catch_all(&self) -> bool {
+        matches!(self, Segment::CatchAll(_) | Segment::OptionalCatchAll(_))
+    }
+
+    pub fn param_name(&self) -> Option<&str> {
+        match self {
+            Segment::Static(_) => None,
+            Segment::Param(name) | Segment::CatchAll(name) | Segment::OptionalCatchAll(name) => {
+                Some(name)
+            }
+        }
+    }
+}
+
+pub fn resolve_segment(segment: &str) -> Segment<'_> {
+    if let Some(name) = segment
+        .strip_prefix("[[...")
+        .and_then(|s| s.strip_suffix("]]"))
+        .filter(|s| !s.is_empty())
+    {
+        return Segment::OptionalCatchAll(name);
+    }
+
+    match segment
+        .strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .filter(|s| !s.is_empty())
+    {
+        Some(name) => match name.strip_prefix("...").filter(|s| !s.is_empty()) {
+            Some(name) => Segment::CatchAll(name),
+            None => Segment::Param(name),
+        },
+        None => Segment::Static(segment),
+    }
+}
+
+/// Dynamic segments are not valid identifiers, so `[id]`, `[...id]` and `[[...id]]` are imported
+/// as modules `_id`, `_all_id` and `_opt_id`.
+fn resolve_module_name(name: &str) -> String {
+    let name = match resolve_segment(name) {
+        Segment::Static(name) => return name.to_string(),
+        Segment::Param(param) => format!("_{param}"),
+        Segment::CatchAll(param) => format!("_all_{param}"),
+        Segment::OptionalCatchAll(param) => format!("_opt_{param}"),
+    };
+
+    name.replace(|c: char| !c.is_ascii_alphanumeric() && c != '_', "_")
 }
 
 fn resolve_import_path(tree: VecDeque<String>) -> String {
-    Vec::from(tree).join("::")
+    Vec::from_iter(tree.iter().map(|r| resolve_module_name(r))).join("::")
+}
+
+#[test]
+fn test_route_specificity() {
+    let l: Vec<VecDeque<String>> = [
+        "docs/[[...rest]]",
+        "users/index",
+        "users/[id]/index",
+        "docs/index",
+        "users/new/index",
+        "[...all]",
+        "items/post",
+        "items/get",
+        "feed/index",
+        "feed/get",
+    ]
+    .iter()
+    .map(|r| r.split("/").map(|s| s.to_string()).collect())
+    .collect();
+
+    let mut tree_list: Vec<&VecDeque<String>> = l.iter().collect();
+    tree_list.sort_by_cached_key(|tree| resolve_specificity(tree));
+
+    assert_eq!(
+        tree_list.iter().map(|t| resolve_file_path(t)).collect::<Vec<String>>(),
+        [
+            "docs/index.rs",
+            "docs/[[...rest]].rs",
+            "feed/index.rs",
+            "feed/get.rs",
+            "items/get.rs",
+            "items/post.rs",
+            "users/new/index.rs",
+            "users/[id]/index.rs",
+            "users/index.rs",
+            "[...all].rs",
+        ]
+    );
+
+    let errors = resolve_conflicts(&tree_list);
+    assert_eq!(errors.len(), 2);
+    assert!(errors[0].contains("`docs/index.rs` and `docs/[[...rest]].rs`"));
+    assert!(errors[1].contains("`feed/index.rs` and `feed/get.rs`"));
 }
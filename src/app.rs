@@ -0,0 +1,264 @@
+use std::{
+    convert::Infallible,
+    future::{poll_fn, Future},
+    io,
+    net::SocketAddr,
+    pin::{pin, Pin},
+    task::Poll,
+};
+
+use warp::{
+    filters::BoxedFilter,
+    hyper::{server::conn::AddrStream, service::make_service_fn, Server},
+    reject::Rejection,
+    Filter, Reply,
+};
+
+use crate::{
+    catch_panic, recovery::recover_using, routing::BoxedRoute, Error, ErrorPages,
+    RouterInnerContext,
+};
+
+/// Result of a startup, readiness or shutdown hook
+pub type HookResult = Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+type Hook = Box<dyn FnOnce() -> Pin<Box<dyn Future<Output = HookResult> + Send>> + Send>;
+
+type ReadyHook =
+    Box<dyn FnOnce(SocketAddr) -> Pin<Box<dyn Future<Output = HookResult> + Send>> + Send>;
+
+type StaticRouter = BoxedFilter<(warp::filters::fs::File,)>;
+
+/**
+   ### Application server
+   Serves the generated routers and their static routers wrapped with `potion::recover` and
+   `potion::catch_panic`. Additional routing trees are added with `with_router` and matched in
+   the order they are added, their static routers being served under their base path.
+   Rejections render the closest error template of the `ErrorPages` added with
+   `with_error_pages`, pages of deeper mounts being tried first.
+
+   Startup hooks run in order before binding, a failing hook aborts `serve`. Readiness hooks
+   receive the bound address once the server accepts connections. On SIGINT or SIGTERM the
+   server stops accepting connections, waits for the pending requests and runs the shutdown
+   hooks in order
+
+   ### Example usage
+   ```ignore
+       let (hb, static_router) = potion::initialize_routing(&project_dir, true)?;
+       let (admin_hb, admin_static_router) = potion::initialize_routing("src/admin", true)?;
+       let hb = Arc::new(hb);
+       let context = Box::new(RouterContext { hb: hb.clone(), db: pool.clone() });
+
+       potion::App::new(context.clone(), router, static_router)
+           .with_router(context, admin_router, admin_static_router, &["admin"])
+           .with_error_pages(potion::ErrorPages::new(hb))
+           .with_error_pages(potion::ErrorPages::new(Arc::new(admin_hb)).with_mount(&["admin"]))
+           .with_dev(true)
+           .on_startup(move || async move { sqlx::migrate!().run(&migrations).await.map_err(Into::into) })
+           .on_ready(|addr| async move {
+               log::info!("Ready on {}", addr);
+               Ok(())
+           })
+           .on_shutdown(move || async move {
+               pool.close().await;
+               Ok(())
+           })
+           .serve(([0, 0, 0, 0], 3030))
+           .await?;
+   ```
+*/
+pub struct App {
+    routes: BoxedRoute,
+    static_router: StaticRouter,
+    error_pages: Vec<ErrorPages>,
+    dev: bool,
+    startup: Vec<Hook>,
+    ready: Vec<ReadyHook>,
+    shutdown: Vec<Hook>,
+}
+
+impl App {
+    pub fn new<F, T, R>(context: RouterInnerContext, router: F, static_router: StaticRouter) -> Self
+    where
+        F: FnOnce(RouterInnerContext) -> T,
+        T: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+        R: Reply + 'static,
+    {
+        Self {
+            routes: router(context).map(Reply::into_response).boxed(),
+            static_router,
+            error_pages: vec![],
+            dev: false,
+            startup: vec![],
+            ready: vec![],
+            shutdown: vec![],
+        }
+    }
+
+    /**
+     * Adds a routing tree generated with `routing!(.., mount = ..)`, serving its static router
+     * under the same base path
+     */
+    pub fn with_router<F, T, R>(
+        mut self,
+        context: RouterInnerContext,
+        router: F,
+        static_router: StaticRouter,
+        mount: &[&str],
+    ) -> Self
+    where
+        F: FnOnce(RouterInnerContext) -> T,
+        T: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+        R: Reply + 'static,
+    {
+        let static_router = mount
+            .iter()
+            .fold(warp::any().boxed(), |filter, segment| {
+                filter.and(warp::path(segment.to_string())).boxed()
+            })
+            .and(static_router)
+            .boxed();
+
+        self.routes = self
+            .routes
+            .or(router(context).map(Reply::into_response))
+            .unify()
+            .boxed();
+        self.static_router = self.static_router.or(static_router).unify().boxed();
+        self
+    }
+
+    /**
+     * Renders rejections with the closest error template of `pages`
+     */
+    pub fn with_error_pages(mut self, pages: ErrorPages) -> Self {
+        self.error_pages.push(pages);
+        self.error_pages
+            .sort_by_key(|pages| std::cmp::Reverse(pages.mount_depth()));
+        self
+    }
+
+    /**
+     * Shows panic messages and backtraces instead of the `500` page
+     */
+    pub fn with_dev(mut self, dev: bool) -> Self {
+        self.dev = dev;
+        self
+    }
+
+    pub fn on_startup<H, Fut>(mut self, hook: H) -> Self
+    where
+        H: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = HookResult> + Send + 'static,
+    {
+        self.startup.push(Box::new(move || Box::pin(hook())));
+        self
+    }
+
+    /**
+     * Runs `hook` with the bound address once the server accepts connections
+     */
+    pub fn on_ready<H, Fut>(mut self, hook: H) -> Self
+    where
+        H: FnOnce(SocketAddr) -> Fut + Send + 'static,
+        Fut: Future<Output = HookResult> + Send + 'static,
+    {
+        self.ready.push(Box::new(move |addr| Box::pin(hook(addr))));
+        self
+    }
+
+    pub fn on_shutdown<H, Fut>(mut self, hook: H) -> Self
+    where
+        H: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = HookResult> + Send + 'static,
+    {
+        self.shutdown.push(Box::new(move || Box::pin(hook())));
+        self
+    }
+
+    /**
+     * Runs the startup hooks and serves on `addr` until SIGINT or SIGTERM. The readiness hooks
+     * run once bound
+     */
+    pub async fn serve(self, addr: impl Into<SocketAddr>) -> io::Result<()> {
+        for hook in self.startup {
+            hook().await.map_err(io::Error::other)?;
+        }
+
+        let pages = self.error_pages;
+        let render = move |path: &str, accept: Option<&str>, error: Error| match pages
+            .iter()
+            .find(|pages| pages.find_template(path, error.code).is_some())
+        {
+            Some(pages) => pages.render(path, accept, error),
+            None => error.negotiate(accept, path),
+        };
+        let routes = recover_using(self.routes.or(self.static_router), render);
+        let routes = catch_panic(routes, self.dev);
+        let make_service = make_service_fn(move |conn: &AddrStream| {
+            let service = routes.with_remote_addr(conn.remote_addr());
+            async move { Ok::<_, Infallible>(service) }
+        });
+
+        let server = Server::try_bind(&addr.into())
+            .map_err(io::Error::other)?
+            .serve(make_service);
+
+        let local_addr = server.local_addr();
+        log::info!("Listening on http://{}", local_addr);
+
+        let ready = self.ready;
+        tokio::spawn(async move {
+            for hook in ready {
+                if let Err(err) = hook(local_addr).await {
+                    log::error!("Readiness hook failed: {}", err);
+                }
+            }
+        });
+
+        if let Err(err) = server.with_graceful_shutdown(shutdown_signal()).await {
+            log::error!("Server error: {}", err);
+        }
+
+        log::info!("Shutting down");
+
+        for hook in self.shutdown {
+            if let Err(err) = hook().await {
+                log::error!("Shutdown hook failed: {}", err);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Resolves on the first SIGINT or SIGTERM
+async fn shutdown_signal() {
+    let mut interrupt = pin!(async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            log::error!("Failed to listen for SIGINT: {}", err);
+            std::future::pending::<()>().await;
+        }
+    });
+
+    let mut terminate = pin!(async {
+        #[cfg(unix)]
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+                return;
+            }
+            Err(err) => log::error!("Failed to listen for SIGTERM: {}", err),
+        }
+
+        std::future::pending::<()>().await;
+    });
+
+    poll_fn(|cx| {
+        match interrupt.as_mut().poll(cx).is_ready() || terminate.as_mut().poll(cx).is_ready() {
+            true => Poll::Ready(()),
+            false => Poll::Pending,
+        }
+    })
+    .await
+}
@@ -1,8 +1,23 @@
-use std::any::Any;
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, RwLock},
+};
 
-use warp::Filter;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use warp::{
+    filters::{path::Tail, BoxedFilter},
+    reject::{Reject, Rejection},
+    Filter,
+};
 
-use crate::storage::Storage;
+use crate::{
+    storage::{resolve_segment, Segment, Storage},
+    HtmlError,
+};
 
 pub type RouterInnerContext = Box<dyn Context + Send + Sync>;
 
@@ -10,6 +25,14 @@ pub trait Context {
     fn box_clone(&self) -> Box<dyn Context + Send + Sync>;
     fn as_any(&self) -> &dyn Any;
     fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    /**
+     * Extensions registered in the context, copied into each `Router`. Implemented by the
+     * `IntoContext` derive for the field marked `#[potion(extensions)]`
+     */
+    fn extensions(&self) -> Option<&Extensions> {
+        None
+    }
 }
 
 impl Clone for Box<dyn Context + Send + Sync> {
@@ -18,42 +41,356 @@ impl Clone for Box<dyn Context + Send + Sync> {
     }
 }
 
+/**
+   ### Type-map of context values
+   Holds one value of each type, so independent modules can register their state without a
+   single struct deriving `IntoContext`. Can be used as the router's context directly, or as a
+   field of a context marked `#[potion(extensions)]`. Each `Router` starts with the extensions
+   of its context, more can be added with `Router::with_extension`
+
+   ### Example usage
+   ```ignore
+       let extensions = potion::Extensions::new().with(pool).with(mailer);
+       let routes = router(Box::new(extensions));
+
+       // Or along other values
+       #[derive(Clone, potion::IntoContext)]
+       pub struct RouterContext {
+           pub hb: Arc<Handlebars<'static>>,
+           #[potion(extensions)]
+           pub extensions: potion::Extensions,
+       }
+
+       // In a route
+       let pool = router.extension::<Pool<Postgres>>().expect("database pool not registered");
+   ```
+*/
+#[derive(Clone, Default)]
+pub struct Extensions {
+    values: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * Inserts `value`, replacing the value of the same type
+     */
+    pub fn insert<T: Send + Sync + Clone + 'static>(&mut self, value: T) {
+        self.values.insert(TypeId::of::<T>(), Arc::new(value));
+    }
+
+    pub fn with<T: Send + Sync + Clone + 'static>(mut self, value: T) -> Self {
+        self.insert(value);
+        self
+    }
+
+    pub fn get<T: Send + Sync + Clone + 'static>(&self) -> Option<&T> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref::<T>())
+    }
+
+    pub fn contains<T: Send + Sync + Clone + 'static>(&self) -> bool {
+        self.values.contains_key(&TypeId::of::<T>())
+    }
+}
+
+impl Context for Extensions {
+    fn box_clone(&self) -> Box<dyn Context + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn extensions(&self) -> Option<&Extensions> {
+        Some(self)
+    }
+}
+
+/**
+   ### Request-scoped state
+   Created for each request by the `potion::catch_panic` service, which `potion::App` serves
+   the routes with, and shared by every filter handling it, so middleware can pass per-request
+   data such as the authenticated user, request id or locale to the routes beneath it. Typed
+   values are read with `get`, while values inserted with `insert_value` are exposed to
+   templates under `REQUEST` by `Page::with_request_state`.
+
+   Filters served with `warp::serve` have no request state, as only a service can give the
+   request its extensions before the filters run
+
+   ### Example usage
+   ```ignore
+       pub fn middleware(router: potion::Router, route: BoxedRoute) -> BoxedRoute {
+           potion::request_state()
+               .and(warp::header::optional::<String>("accept-language"))
+               .map(|state: potion::RequestState, locale: Option<String>| {
+                   state.insert_value("locale", locale.unwrap_or(String::from("en")));
+               })
+               .untuple_one()
+               .and(route)
+               .boxed()
+       }
+
+       // In a route
+       router.with_request_state().map(|router: Router, state: potion::RequestState| {
+           let user = state.get::<User>();
+       })
+   ```
+*/
+#[derive(Clone, Default)]
+pub struct RequestState {
+    extensions: Arc<RwLock<Extensions>>,
+    values: Arc<RwLock<Map<String, Value>>>,
+}
+
+impl RequestState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /**
+     * Inserts `value`, replacing the value of the same type
+     */
+    pub fn insert<T: Send + Sync + Clone + 'static>(&self, value: T) {
+        match self.extensions.write() {
+            Ok(mut extensions) => extensions.insert(value),
+            Err(err) => log::error!("Failed to insert request state: {}", err),
+        }
+    }
+
+    pub fn get<T: Send + Sync + Clone + 'static>(&self) -> Option<T> {
+        self.extensions
+            .read()
+            .ok()
+            .and_then(|extensions| extensions.get::<T>().cloned())
+    }
+
+    pub fn contains<T: Send + Sync + Clone + 'static>(&self) -> bool {
+        self.extensions
+            .read()
+            .is_ok_and(|extensions| extensions.contains::<T>())
+    }
+
+    /**
+     * Inserts a value exposed to templates as `REQUEST.<key>`
+     */
+    pub fn insert_value(&self, key: &str, value: impl Serialize) {
+        let value = match serde_json::to_value(value) {
+            Ok(value) => value,
+            Err(err) => return log::error!("Failed to serialize request value {}: {}", key, err),
+        };
+
+        match self.values.write() {
+            Ok(mut values) => {
+                values.insert(key.to_string(), value);
+            }
+            Err(err) => log::error!("Failed to insert request value {}: {}", key, err),
+        }
+    }
+
+    /**
+     * Values inserted with `insert_value`, as an object
+     */
+    pub fn values(&self) -> Value {
+        self.values
+            .read()
+            .map(|values| Value::Object(values.clone()))
+            .unwrap_or_default()
+    }
+}
+
+/**
+ * Extracts the state of the request. Rejects with `500` outside of `potion::catch_panic`,
+ * as the state wouldn't be shared with the other filters
+ */
+pub fn request_state() -> impl Filter<Extract = (RequestState,), Error = Rejection> + Clone {
+    warp::ext::optional::<RequestState>().and_then(|state: Option<RequestState>| async move {
+        state.ok_or_else(|| {
+            log::error!("Request state read outside of potion::catch_panic");
+            warp::reject::custom(HtmlError::InternalServerError.default())
+        })
+    })
+}
+
+/// Address of the client, set by `potion::catch_panic`
+#[derive(Clone, Copy)]
+pub(crate) struct RemoteAddr(pub(crate) SocketAddr);
+
+/**
+ * Address of the client. Unlike `warp::addr::remote`, also set beneath `potion::catch_panic`
+ */
+pub fn remote_addr() -> impl Filter<Extract = (Option<SocketAddr>,), Error = Infallible> + Clone {
+    warp::addr::remote()
+        .and(warp::ext::optional::<RemoteAddr>())
+        .map(
+            |remote: Option<SocketAddr>, extension: Option<RemoteAddr>| {
+                remote.or(extension.map(|RemoteAddr(remote)| remote))
+            },
+        )
+}
+
+/// Rejection of `Router::match_path` marking a match
+#[derive(Debug)]
+struct PathMatched;
+
+impl Reject for PathMatched {}
+
 #[derive(Clone)]
 pub struct Router {
     context: Box<dyn Context + Send + Sync>,
+    extensions: Extensions,
     pub storage: Storage,
 }
 
 impl Router {
     pub fn new(context: Box<dyn Context + Send + Sync>, path: &[&str]) -> Self {
+        Self::from_existing(context, Storage::from(path))
+    }
+
+    pub fn with_mount(mut self, mount: &[&str]) -> Self {
+        self.storage = self.storage.with_mount(mount);
+        self
+    }
+
+    /**
+     * Creates a router with the extensions registered in the context
+     */
+    pub fn from_existing(context: Box<dyn Context + Send + Sync>, storage: Storage) -> Self {
         Self {
+            extensions: context.extensions().cloned().unwrap_or_default(),
             context,
-            storage: Storage::from(path),
+            storage,
         }
     }
 
-    pub fn from_existing(context: Box<dyn Context + Send + Sync>, storage: Storage) -> Self {
-        Self { context, storage }
+    /**
+     * Returns the context as `U`, or `None` when the context is of another type
+     */
+    pub fn try_downcast<U: Context + Send + Sync + 'static>(&self) -> Option<&U> {
+        self.context.as_any().downcast_ref::<U>()
+    }
+
+    pub fn try_downcast_mut<U: Context + Send + Sync + 'static>(&mut self) -> Option<&mut U> {
+        self.context.as_any_mut().downcast_mut::<U>()
+    }
+
+    /**
+     * Inserts an extension, replacing the extension of the same type
+     */
+    pub fn insert<T: Send + Sync + Clone + 'static>(&mut self, value: T) {
+        self.extensions.insert(value);
+    }
+
+    pub fn with_extension<T: Send + Sync + Clone + 'static>(mut self, value: T) -> Self {
+        self.insert(value);
+        self
+    }
+
+    /**
+     * Returns the extension of type `T`, inserted in the router or registered in the context
+     */
+    pub fn extension<T: Send + Sync + Clone + 'static>(&self) -> Option<&T> {
+        self.extensions.get::<T>()
     }
 
     pub fn downcast<U: Context + Send + Sync + 'static>(&self) -> &U {
-        self.context
-            .as_any()
-            .downcast_ref::<U>()
+        self.try_downcast::<U>()
             .expect("downcasting from Router<dyn Context>")
     }
 
     pub fn downcast_mut<U: Context + Send + Sync + 'static>(&mut self) -> &mut U {
-        self.context
-            .as_any_mut()
-            .downcast_mut::<U>()
+        self.try_downcast_mut::<U>()
             .expect("downcasting from Router<dyn Context>")
     }
 
-    pub fn with_context(
+    /**
+     * Clones the router into each request. Dynamic `[name]` and catch-all segments are matched
+     * with `warp::path::param` and `warp::path::tail`, their values can be read with
+     * `storage.get_param`. The dynamic segments are consumed, so call it once per route,
+     * before other path filters.
+     *
+     * The generated router has already matched the static segments of the route and checked
+     * the whole path, so `initialize` doesn't match its path itself, e.g. `warp::path("hello")`
+     * in `hello/index.rs` never matches
+     */
+    pub fn with_context(&self) -> impl Filter<Extract = (Self,), Error = Rejection> + Clone {
+        let router = self.clone();
+        self.dynamic_path().map(move |values: Vec<String>| Self {
+            storage: router.storage.with_params(values),
+            ..router.clone()
+        })
+    }
+
+    /**
+     * Matches the dynamic segments of the route without consuming them, so the generated
+     * router only links the route to paths `with_context` resolves
+     */
+    pub fn match_path(&self) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+        self.dynamic_path()
+            .and_then(|_| async { Err::<(), _>(warp::reject::custom(PathMatched)) })
+            // Recovering resets the path, the rejection only marks the successful match
+            .recover(|rejection: Rejection| async move {
+                match rejection.find::<PathMatched>() {
+                    Some(_) => Ok(()),
+                    None => Err(rejection),
+                }
+            })
+            .unify()
+            .untuple_one()
+    }
+
+    /// Matches the route's segments from its first dynamic segment to the end of the path,
+    /// extracting the raw values of the dynamic segments
+    fn dynamic_path(&self) -> BoxedFilter<(Vec<String>,)> {
+        self.storage
+            .get_dynamic_segments()
+            .into_iter()
+            .fold(warp::any().map(Vec::new).boxed(), |filter, segment| {
+                match resolve_segment(segment) {
+                    Segment::Static(name) => filter.and(warp::path(name.to_string())).boxed(),
+                    Segment::Param(_) => filter
+                        .and(warp::path::param::<String>())
+                        .map(|mut values: Vec<String>, value: String| {
+                            values.push(value);
+                            values
+                        })
+                        .boxed(),
+                    segment => {
+                        let optional = matches!(segment, Segment::OptionalCatchAll(_));
+
+                        filter
+                            .and(warp::path::tail())
+                            .and_then(move |mut values: Vec<String>, tail: Tail| async move {
+                                if tail.as_str().is_empty() && !optional {
+                                    return Err(warp::reject::not_found());
+                                }
+
+                                values.push(tail.as_str().to_string());
+                                Ok(values)
+                            })
+                            .boxed()
+                    }
+                }
+            })
+            .and(warp::path::end())
+            .boxed()
+    }
+
+    /**
+     * `with_context` along with the state of the request, see `potion::RequestState`
+     */
+    pub fn with_request_state(
         &self,
-    ) -> impl Filter<Extract = (Self,), Error = std::convert::Infallible> + Clone {
-        let router = Self::from_existing(self.context.box_clone(), self.storage.clone());
-        warp::any().map(move || router.clone())
+    ) -> impl Filter<Extract = (Self, RequestState), Error = Rejection> + Clone {
+        self.with_context().and(request_state())
     }
 }
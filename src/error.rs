@@ -1,18 +1,119 @@
-use std::fmt::{self, Display};
+use std::{
+    fmt::{self, Display},
+    sync::{Arc, RwLock},
+};
 
 use http::StatusCode;
-use warp::reject::Rejection;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use warp::{reject::Rejection, Reply};
 
-pub const ERROR_CODE_INFO: &[(i16, &str)] = &[
+use crate::Redirect;
+
+/// Messages shown on error pages when no message is configured for the code
+pub const DEFAULT_ERROR_MESSAGES: &[(i16, &str)] = &[
     (400, "Invalid request; The request failed to contain or contained invalid payload. This shouldn't happen with normal use, so take your time to report this issue if you did not modify request parameters by hand.\
      The server responded with the following information about the issue: "),
     (401, "Invalid credentials; The browser should redirect you in a second.... if it does,'t this is a bug! Please report this below: "),
     (403, "Permission denied; You were not allowed to perform this action. Unless you were trying to do something you are not allowed to, you should report this below: "),
-    (500, "Internal server error, this error was automatically reported to our system. The server responded with the following information about the issue: ")
+    (404, "Not found; The page you were looking for doesn't exist or has been moved. "),
+    (405, "Method not allowed; The requested page doesn't support this kind of request. The server responded with the following information about the issue: "),
+    (409, "Conflict; The request conflicts with the current state of the resource. The server responded with the following information about the issue: "),
+    (410, "Gone; The page you were looking for has been permanently removed. "),
+    (411, "Length required; The request is missing a content-length header. "),
+    (413, "Payload too large; The request exceeded the maximum allowed size. "),
+    (415, "Unsupported media type; The request's content-type is not supported. "),
+    (422, "Unprocessable entity; The request contained values that couldn't be processed. The server responded with the following information about the issue: "),
+    (429, "Too many requests; Slow down and try again in a moment. "),
+    (500, "Internal server error, this error was automatically reported to our system. The server responded with the following information about the issue: "),
+    (501, "Not implemented; The server doesn't support this functionality yet. "),
+    (502, "Bad gateway; The server received an invalid response from an upstream server. "),
+    (503, "Service unavailable; The server is temporarily unable to handle the request, try again later. "),
+    (504, "Gateway timeout; An upstream server failed to respond in time. "),
 ];
 
 pub const VALID_ERROR_CODES: &[i16] = &[401, 403];
 
+static ERROR_MESSAGES: RwLock<Option<ErrorMessages>> = RwLock::new(None);
+
+/**
+   ### Table of messages shown on error pages
+   Starts from `DEFAULT_ERROR_MESSAGES` and is installed once during startup
+
+   ### Example usage
+   ```ignore
+       potion::ErrorMessages::default()
+           .with_message(404, "Nothing to see here. ")
+           .with_message(503, "Down for maintenance. ")
+           .install();
+   ```
+*/
+#[derive(Debug, Clone)]
+pub struct ErrorMessages {
+    messages: Vec<(i16, String)>,
+}
+
+impl Default for ErrorMessages {
+    fn default() -> Self {
+        Self {
+            messages: DEFAULT_ERROR_MESSAGES
+                .iter()
+                .map(|(code, message)| (*code, message.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl ErrorMessages {
+    /**
+     * Creates a table without any messages
+     */
+    pub fn empty() -> Self {
+        Self { messages: vec![] }
+    }
+
+    /**
+     * Sets the message of a code, replacing the existing one
+     */
+    pub fn with_message(mut self, code: i16, message: &str) -> Self {
+        self.messages.retain(|(c, _)| *c != code);
+        self.messages.push((code, message.to_string()));
+        self
+    }
+
+    pub fn get(&self, code: i16) -> Option<&str> {
+        self.messages
+            .iter()
+            .find_map(|(c, message)| if *c == code { Some(message.as_str()) } else { None })
+    }
+
+    /**
+     * Uses the table for every error rendered afterwards
+     */
+    pub fn install(self) {
+        match ERROR_MESSAGES.write() {
+            Ok(mut messages) => *messages = Some(self),
+            Err(err) => log::error!("Failed to install error messages: {}", err),
+        }
+    }
+
+    /**
+     * Message of a code in the installed table, or in `DEFAULT_ERROR_MESSAGES` when none is installed
+     */
+    pub fn lookup(code: i16) -> Option<String> {
+        if let Ok(messages) = ERROR_MESSAGES.read() {
+            if let Some(messages) = messages.as_ref() {
+                return messages.get(code).map(|message| message.to_string());
+            }
+        }
+
+        DEFAULT_ERROR_MESSAGES.iter().find_map(|(c, message)| match *c == code {
+            true => Some(message.to_string()),
+            false => None,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 /**
    ### Enum for representing the most common html errorcodes
@@ -26,6 +127,8 @@ pub const VALID_ERROR_CODES: &[i16] = &[401, 403];
 
    ### Example usage
    ```
+       use potion::HtmlError;
+
        fn test() -> Result<(), potion::Error> {
            return Err(HtmlError::Unauthorized.default())
        }
@@ -33,25 +136,85 @@ pub const VALID_ERROR_CODES: &[i16] = &[401, 403];
        fn test2() -> Result<(), potion::Error> {
            return Err(HtmlError::Unauthorized.new("Invalid password"))
        }
+
+       fn test3() -> Result<(), potion::Error> {
+           return Err(HtmlError::TooManyRequests.default().with_retry_after(30))
+       }
    ```
 */
 pub enum HtmlError {
-    Unauthorized,
+    /// 400
     InvalidRequest,
+    /// 401
     InvalidSession,
+    /// 403
+    Unauthorized,
+    /// 404
+    NotFound,
+    /// 405, attach the allowed methods with `Error::with_allow`
+    MethodNotAllowed,
+    /// 409
+    Conflict,
+    /// 410
+    Gone,
+    /// 411
+    LengthRequired,
+    /// 413
+    PayloadTooLarge,
+    /// 415
+    UnsupportedMediaType,
+    /// 422
+    UnprocessableEntity,
+    /// 429, attach the delay with `Error::with_retry_after`
+    TooManyRequests,
+    /// 500
     InternalServerError,
+    /// 501
+    NotImplemented,
+    /// 502
+    BadGateway,
+    /// 503, attach the delay with `Error::with_retry_after`
+    ServiceUnavailable,
+    /// 504
+    GatewayTimeout,
 }
 
 impl HtmlError {
+    pub fn code(self) -> i16 {
+        match self {
+            Self::InvalidRequest => 400,
+            Self::InvalidSession => 401,
+            Self::Unauthorized => 403,
+            Self::NotFound => 404,
+            Self::MethodNotAllowed => 405,
+            Self::Conflict => 409,
+            Self::Gone => 410,
+            Self::LengthRequired => 411,
+            Self::PayloadTooLarge => 413,
+            Self::UnsupportedMediaType => 415,
+            Self::UnprocessableEntity => 422,
+            Self::TooManyRequests => 429,
+            Self::InternalServerError => 500,
+            Self::NotImplemented => 501,
+            Self::BadGateway => 502,
+            Self::ServiceUnavailable => 503,
+            Self::GatewayTimeout => 504,
+        }
+    }
+
     /**
      * Convert into `potion::Error` with default information
      */
     pub fn default(self) -> Error {
         match self {
-            HtmlError::InvalidSession => Error::new(401, "Invalid credentials", None),
-            HtmlError::Unauthorized => Error::new(403, "Permission denied", None),
-            HtmlError::InvalidRequest => Error::new(400, "Invalid request", None),
-            HtmlError::InternalServerError => Error::new(500, "Internal server error", None),
+            Self::InvalidSession => self.new("Invalid credentials"),
+            Self::Unauthorized => self.new("Permission denied"),
+            Self::InvalidRequest => self.new("Invalid request"),
+            _ => {
+                let status = StatusCode::from_u16(self.code() as u16)
+                    .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+                self.new(status.canonical_reason().unwrap_or("Unknown error"))
+            }
         }
     }
 
@@ -59,30 +222,20 @@ impl HtmlError {
      * Convert into `potion::Error` with information
      */
     pub fn new(self, info: &str) -> Error {
-        match self {
-            Self::InvalidSession => Error::new(401, info, None),
-            Self::Unauthorized => Error::new(403, info, None),
-            Self::InvalidRequest => Error::new(400, info, None),
-            Self::InternalServerError => Error::new(500, info, None),
-        }
+        Error::new(self.code(), info, None)
     }
 
     /**
      * Convert into `potion::Error` with information
      */
     pub fn redirect(self, info: &str, redirect: &str) -> Error {
-        match self {
-            HtmlError::Unauthorized => Error::new(401, info, Some(redirect.to_string())),
-            HtmlError::InvalidSession => Error::new(403, info, Some(redirect.to_string())),
-            HtmlError::InvalidRequest => Error::new(400, info, Some(redirect.to_string())),
-            HtmlError::InternalServerError => Error::new(500, info, Some(redirect.to_string())),
-        }
+        Error::new(self.code(), info, Some(redirect.to_string()))
     }
 }
 
 impl Display for HtmlError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "({}: {:?})", self, self.default())
+        write!(f, "({:?}: {})", self, self.code())
     }
 }
 
@@ -92,17 +245,204 @@ impl std::error::Error for HtmlError {}
 pub struct Error {
     pub code: i16,
     pub info: Option<String>,
-    pub redirect: Option<String>,
+    pub redirect: Option<Redirect>,
+    /// Extra members of the `application/problem+json` body
+    pub fields: Map<String, Value>,
+    /// Headers sent along the error response, e.g. `Retry-After` or `Allow`
+    pub headers: Vec<(String, String)>,
+    /// Error that caused this error, reported but never shown to the client
+    pub source: Option<Arc<dyn std::error::Error + Send + Sync + 'static>>,
 }
 
 impl Error {
+    /**
+     * Creates an error, redirecting with `303 See Other` to `redirect` when given
+     */
     pub fn new(code: i16, info: &str, redirect: Option<String>) -> Self {
         Self {
             code,
             info: Some(info.to_string()),
-            redirect,
+            redirect: redirect.map(|location| Redirect::see_other(&location)),
+            fields: Map::new(),
+            headers: vec![],
+            source: None,
+        }
+    }
+
+    /**
+     * Attaches the error that caused this error
+     */
+    pub fn with_source(mut self, source: impl std::error::Error + Send + Sync + 'static) -> Self {
+        self.source = Some(Arc::new(source));
+        self
+    }
+
+    /**
+     * Responds with `redirect` instead of the error page
+     */
+    pub fn with_redirect(mut self, redirect: Redirect) -> Self {
+        self.redirect = Some(redirect);
+        self
+    }
+
+    /**
+     * Sends a header along the error response
+     */
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /**
+     * Sets the `Retry-After` header, in seconds, e.g. for `429` and `503` errors
+     */
+    pub fn with_retry_after(self, seconds: u64) -> Self {
+        self.with_header("Retry-After", &seconds.to_string())
+    }
+
+    /**
+     * Sets the `Allow` header listing the methods served, e.g. for `405` errors
+     */
+    pub fn with_allow(self, methods: &[&str]) -> Self {
+        self.with_header("Allow", &methods.join(", "))
+    }
+
+    /**
+     * Attaches a structured field to the error, included in `application/problem+json`
+     * responses. Fields named `type` or `title` override the defaults
+     */
+    pub fn with_field(mut self, key: &str, value: impl Serialize) -> Self {
+        self.fields.insert(
+            key.to_string(),
+            serde_json::to_value(value).unwrap_or(Value::Null),
+        );
+        self
+    }
+
+    /**
+     * RFC 7807 problem details of the error, `instance` being the requested path
+     */
+    pub fn to_problem(&self, instance: &str) -> Value {
+        let mut problem = json!({
+            "type": "about:blank",
+            "title": self.status_code().canonical_reason().unwrap_or("Unknown error"),
+            "status": self.code,
+            "detail": self.info,
+            "instance": instance,
+        });
+
+        for (key, value) in &self.fields {
+            problem[key] = value.clone();
+        }
+
+        problem
+    }
+
+    /**
+     * Renders the error as `application/problem+json`
+     */
+    pub fn into_problem_response(self, instance: &str) -> warp::reply::Response {
+        let mut response = warp::reply::with_status(
+            warp::reply::json(&self.to_problem(instance)),
+            self.status_code(),
+        )
+        .into_response();
+
+        response.headers_mut().insert(
+            warp::http::header::CONTENT_TYPE,
+            warp::http::HeaderValue::from_static("application/problem+json"),
+        );
+        self.append_headers(response)
+    }
+
+    /**
+     * Adds the headers of the error to `response`
+     */
+    pub fn append_headers(&self, mut response: warp::reply::Response) -> warp::reply::Response {
+        for (name, value) in &self.headers {
+            match (
+                warp::http::HeaderName::from_bytes(name.as_bytes()),
+                warp::http::HeaderValue::from_str(value),
+            ) {
+                (Ok(name), Ok(value)) => {
+                    response.headers_mut().append(name, value);
+                }
+                _ => log::error!("Invalid error header {}: {}", name, value),
+            }
+        }
+
+        response
+    }
+
+    /**
+     * Renders the error as `application/problem+json` when the `Accept` header prefers JSON,
+     * otherwise as the error page. Both vary on `Accept`, redirects excepted
+     */
+    pub fn negotiate(self, accept: Option<&str>, instance: &str) -> warp::reply::Response {
+        if self.redirect.is_some() {
+            return self.into_response();
+        }
+
+        match accept.is_some_and(prefers_json) {
+            true => with_vary_accept(self.into_problem_response(instance)),
+            false => with_vary_accept(self.into_response()),
+        }
+    }
+
+    /**
+     * Description of the error code shown on error pages
+     */
+    pub fn description(&self) -> String {
+        ErrorMessages::lookup(self.code).unwrap_or(String::from("Unknown error"))
+    }
+
+    pub fn status_code(&self) -> warp::http::StatusCode {
+        warp::http::StatusCode::from_u16(self.code as u16)
+            .unwrap_or(warp::http::StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+/**
+ * Whether an `Accept` header prefers JSON over HTML. Ties, e.g. a wildcard, prefer HTML
+ */
+pub fn prefers_json(accept: &str) -> bool {
+    let mut html = 0.0;
+    let mut json = 0.0;
+
+    for range in accept.split(',') {
+        let mut parts = range.split(';').map(str::trim);
+        let media = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let quality = parts
+            .find_map(|p| p.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        match media.as_str() {
+            "text/html" | "application/xhtml+xml" | "text/*" => html = f32::max(html, quality),
+            "application/json" | "application/problem+json" | "application/*" => {
+                json = f32::max(json, quality)
+            }
+            "*/*" => {
+                html = f32::max(html, quality);
+                json = f32::max(json, quality);
+            }
+            _ => {}
         }
     }
+
+    json > html
+}
+
+/**
+ * Marks a response whose body depends on the `Accept` header, so shared caches keep the HTML
+ * and JSON renditions apart
+ */
+pub(crate) fn with_vary_accept(mut response: warp::reply::Response) -> warp::reply::Response {
+    response.headers_mut().append(
+        warp::http::header::VARY,
+        warp::http::HeaderValue::from_static("accept"),
+    );
+    response
 }
 
 impl Display for Error {
@@ -111,38 +451,25 @@ impl Display for Error {
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.source
+            .as_ref()
+            .map(|source| source.as_ref() as &(dyn std::error::Error + 'static))
+    }
+}
 impl warp::reject::Reject for Error {}
 
 impl warp::Reply for Error {
     fn into_response(self) -> warp::reply::Response {
-        if let Some(url) = self.redirect {
-            return warp::reply::with_header(
-                warp::redirect(warp::http::Uri::from_static(url.leak())),
-                "Cache-Control",
-                "no-cache, must-revalidate",
-            )
-            .into_response();
+        if let Some(redirect) = self.redirect.clone() {
+            return self.append_headers(redirect.into_response());
         };
         let code = self.code;
-        let info = self.info.unwrap_or(String::from("Unknown error"));
-
-        if !VALID_ERROR_CODES.contains(&code) {
-            log::error!("Error: {:?}", code)
-        }
-
-        let description = ERROR_CODE_INFO
-            .iter()
-            .find_map(|(code, info)| {
-                if &self.code != code {
-                    return None;
-                }
+        let description = handlebars::html_escape(&self.description());
+        let info = handlebars::html_escape(self.info.as_deref().unwrap_or("Unknown error"));
 
-                Some(*info)
-            })
-            .unwrap_or("Unknown error");
-
-        warp::reply::html(format!(
+        let html = warp::reply::html(format!(
             r#"
             <!DOCTYPE html>
             <html>
@@ -163,8 +490,9 @@ impl warp::Reply for Error {
                 </body>
             </html>
         "#
-        ))
-        .into_response()
+        ));
+
+        self.append_headers(warp::reply::with_status(html, self.status_code()).into_response())
     }
 }
 
@@ -194,8 +522,193 @@ impl Into<Rejection> for TypeError {
     }
 }
 
+/**
+ * Converts a rejection into `potion::Error`. Rejections by `potion::Error` are cloned and
+ * warp's built-in rejections are mapped to their status codes
+ */
+impl From<&Rejection> for Error {
+    fn from(rejection: &Rejection) -> Self {
+        use warp::reject::*;
+
+        if let Some(error) = rejection.find::<Error>() {
+            return error.clone();
+        }
+
+        // warp doesn't expose the methods of the rejecting filters, so no `Allow` header can be
+        // sent. Method files of the routing tree respond with their own `405` listing them
+        if let Some(e) = rejection.find::<MethodNotAllowed>() {
+            return HtmlError::MethodNotAllowed.new(&e.to_string());
+        }
+
+        if let Some(e) = rejection.find::<PayloadTooLarge>() {
+            return HtmlError::PayloadTooLarge.new(&e.to_string());
+        }
+
+        if let Some(e) = rejection.find::<UnsupportedMediaType>() {
+            return HtmlError::UnsupportedMediaType.new(&e.to_string());
+        }
+
+        if let Some(e) = rejection.find::<LengthRequired>() {
+            return HtmlError::LengthRequired.new(&e.to_string());
+        }
+
+        if let Some(e) = rejection.find::<MissingHeader>() {
+            return HtmlError::InvalidRequest.new(&e.to_string());
+        }
+
+        if let Some(e) = rejection.find::<InvalidHeader>() {
+            return HtmlError::InvalidRequest.new(&e.to_string());
+        }
+
+        if let Some(e) = rejection.find::<MissingCookie>() {
+            return HtmlError::InvalidRequest.new(&e.to_string());
+        }
+
+        if let Some(e) = rejection.find::<InvalidQuery>() {
+            return HtmlError::InvalidRequest.new(&e.to_string());
+        }
+
+        // The message echoes the payload, it's only kept for reporting
+        if let Some(e) = rejection.find::<warp::body::BodyDeserializeError>() {
+            return HtmlError::InvalidRequest
+                .default()
+                .with_source(TypeError::new(&e.to_string()));
+        }
+
+        if let Some(e) = rejection.find::<warp::cors::CorsForbidden>() {
+            return HtmlError::Unauthorized.new(&e.to_string());
+        }
+
+        if rejection.is_not_found() {
+            return HtmlError::NotFound.default();
+        }
+
+        log::error!("Unhandled rejection: {:?}", rejection);
+        HtmlError::InternalServerError.default()
+    }
+}
+
+/**
+ * Redirects by rejecting, e.g. `return Err(Redirect::see_other("/login").into())`
+ */
+impl From<Redirect> for Error {
+    fn from(redirect: Redirect) -> Self {
+        let status = redirect.kind.status_code();
+
+        Self {
+            code: status.as_u16() as i16,
+            info: status.canonical_reason().map(|reason| reason.to_string()),
+            redirect: Some(redirect),
+            fields: Map::new(),
+            headers: vec![],
+            source: None,
+        }
+    }
+}
+
+impl From<TypeError> for Error {
+    fn from(error: TypeError) -> Self {
+        HtmlError::InvalidRequest.new(&error.info)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        HtmlError::InternalServerError.default().with_source(error)
+    }
+}
+
+/**
+ * Syntax and data errors are caused by the request's payload and map to `400`. The message
+ * echoes the payload, so it's only kept in the source
+ */
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        match error.classify() {
+            serde_json::error::Category::Io => {
+                HtmlError::InternalServerError.default().with_source(error)
+            }
+            _ => HtmlError::InvalidRequest.default().with_source(error),
+        }
+    }
+}
+
+impl From<handlebars::RenderError> for Error {
+    fn from(error: handlebars::RenderError) -> Self {
+        HtmlError::InternalServerError.default().with_source(error)
+    }
+}
+
+impl From<handlebars::TemplateError> for Error {
+    fn from(error: handlebars::TemplateError) -> Self {
+        HtmlError::InternalServerError.default().with_source(error)
+    }
+}
+
+impl From<std::num::ParseIntError> for Error {
+    fn from(error: std::num::ParseIntError) -> Self {
+        HtmlError::InvalidRequest
+            .new(&error.to_string())
+            .with_source(error)
+    }
+}
+
+impl From<std::num::ParseFloatError> for Error {
+    fn from(error: std::num::ParseFloatError) -> Self {
+        HtmlError::InvalidRequest
+            .new(&error.to_string())
+            .with_source(error)
+    }
+}
+
+impl From<std::str::ParseBoolError> for Error {
+    fn from(error: std::str::ParseBoolError) -> Self {
+        HtmlError::InvalidRequest
+            .new(&error.to_string())
+            .with_source(error)
+    }
+}
+
+/**
+   ### Handler result adapter
+   Turns the result of a handler into a reply, rejecting with the `potion::Error` so it's
+   rendered by `potion::recover`. Lets handlers use `?` on any error convertible into `potion::Error`
+
+   ### Example usage
+   ```ignore
+       warp::path!("users" / String).and_then(|id: String| async move {
+           potion::respond(async {
+               let id: i32 = id.parse()?;
+               let user = serde_json::to_string(&load_user(id).await?)?;
+               Ok(user)
+           }.await)
+       })
+   ```
+*/
+pub fn respond<R: Reply>(result: Result<R, Error>) -> Result<warp::reply::Response, Rejection> {
+    match result {
+        Ok(reply) => Ok(reply.into_response()),
+        Err(error) => Err(warp::reject::custom(error)),
+    }
+}
+
 impl Into<http::StatusCode> for Error {
     fn into(self) -> http::StatusCode {
         StatusCode::from_u16(self.code as u16).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
     }
 }
+
+#[test]
+fn test_prefers_json() {
+    assert!(prefers_json("application/json"));
+    assert!(prefers_json("application/problem+json"));
+    assert!(prefers_json("text/html;q=0.5, application/json"));
+    assert!(prefers_json("application/*, text/html;q=0.9"));
+
+    assert!(!prefers_json(""));
+    assert!(!prefers_json("text/html"));
+    assert!(!prefers_json("*/*"));
+    assert!(!prefers_json("text/html, application/json"));
+    assert!(!prefers_json("application/json;q=0.2, text/*"));
+    assert!(!prefers_json("text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"));
+}
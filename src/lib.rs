@@ -4,12 +4,18 @@ extern crate macros;
 #[cfg(feature = "typescript")]
 extern crate wsc;
 
+pub mod app;
 pub mod context;
 pub mod error;
 pub mod file_system;
 pub mod form_data;
+pub mod overlay;
 pub mod page;
 pub mod pagination;
+pub mod panic;
+pub mod recovery;
+pub mod redirect;
+pub mod report;
 pub mod routing;
 pub mod storage;
 pub mod uri;
@@ -19,13 +25,18 @@ pub mod utility;
 pub use macros::*;
 
 #[cfg(feature = "routing")]
-pub use routing::initialize_routing;
+pub use routing::{initialize_routing, RouteInfo};
 
 #[cfg(feature = "typescript")]
 pub use wsc::*;
 
+pub use app::*;
 pub use context::*;
 pub use error::*;
 pub use form_data::*;
 pub use page::*;
+pub use panic::*;
+pub use recovery::*;
+pub use redirect::*;
+pub use report::*;
 pub use uri::*;
@@ -0,0 +1,212 @@
+use std::{fs, path::PathBuf, sync::RwLock};
+
+use handlebars::{
+    html_escape, Handlebars, RenderError, RenderErrorReason, Template, TemplateError,
+};
+use warp::{http::StatusCode, reply::Response, Reply};
+
+use crate::{report::UnreportedError, Error};
+
+/// Template files by template name of each routing tree, registered in dev mode to show
+/// snippets of failing templates
+static TEMPLATE_FILES: RwLock<Vec<Vec<(String, PathBuf)>>> = RwLock::new(vec![]);
+
+/// Template holding the index of the routing tree's files in `TEMPLATE_FILES`
+const TEMPLATE_FILES_TEMPLATE: &str = "potion::template_files";
+
+/// Scripts that failed to compile in dev mode
+static SCRIPT_ERRORS: RwLock<Vec<ScriptError>> = RwLock::new(vec![]);
+
+/**
+ * TypeScript file that failed to compile, with the swc diagnostics
+ */
+#[derive(Debug, Clone)]
+pub struct ScriptError {
+    pub file: PathBuf,
+    pub diagnostics: String,
+}
+
+/**
+ * Registers the template files of the routing tree loaded into `hb`, next to the files of the
+ * other trees
+ */
+pub(crate) fn register_template_files(
+    hb: &mut Handlebars<'static>,
+    files: Vec<(String, PathBuf)>,
+) -> Result<(), TemplateError> {
+    let index = match TEMPLATE_FILES.write() {
+        Ok(mut templates) => {
+            templates.push(files);
+            templates.len() - 1
+        }
+        Err(_) => return Ok(()),
+    };
+
+    hb.register_template_string(TEMPLATE_FILES_TEMPLATE, index.to_string())
+}
+
+#[cfg(feature = "typescript")]
+pub(crate) fn register_script_error(error: ScriptError) {
+    if let Ok(mut errors) = SCRIPT_ERRORS.write() {
+        errors.push(error);
+    }
+}
+
+/**
+ * Scripts that failed to compile during `initialize_routing` in dev mode
+ */
+pub fn script_errors() -> Vec<ScriptError> {
+    SCRIPT_ERRORS
+        .read()
+        .map(|errors| errors.clone())
+        .unwrap_or_default()
+}
+
+/**
+ * Responds to a failed render of a template of `hb`. Dev mode shows the overlay with the
+ * template, position and snippet. Production logs the error and responds with `500`, reported
+ * and rendered with the error pages by `potion::recover`
+ */
+pub fn template_error_response(err: RenderError, hb: &Handlebars<'_>) -> Response {
+    log::error!("{}", err);
+
+    if !hb.dev_mode() {
+        let error = Error::from(err);
+        let mut response = error.clone().into_response();
+        response.extensions_mut().insert(UnreportedError(error));
+        return response;
+    }
+
+    let (name, position, message) = match err.reason() {
+        RenderErrorReason::TemplateError(e) => (e.name().cloned(), e.pos(), err.to_string()),
+        // Templates failing to parse at startup aren't registered, parse them again for the error
+        RenderErrorReason::TemplateNotFound(name) => match parse_template_file(hb, name) {
+            Some(e) => (Some(name.clone()), e.pos(), e.to_string()),
+            None => (Some(name.clone()), None, err.to_string()),
+        },
+        _ => (
+            err.template_name.clone(),
+            err.line_no.map(|line| (line, err.column_no.unwrap_or(0))),
+            err.to_string(),
+        ),
+    };
+
+    let name = name.unwrap_or(String::from("Unnamed template"));
+    let snippet = position
+        .and_then(|(line, column)| template_snippet(hb, &name, line, column))
+        .unwrap_or_default();
+
+    let location = match position {
+        Some((line, column)) => format!("line {line}, col {column}"),
+        None => String::new(),
+    };
+
+    render_overlay(
+        "Template error",
+        &[(
+            format!("{} {}", name, location),
+            message,
+            snippet,
+        )],
+    )
+}
+
+/**
+ * Overlay listing the scripts that failed to compile
+ */
+pub fn script_errors_response(errors: &[ScriptError]) -> Response {
+    render_overlay(
+        "TypeScript error",
+        &errors
+            .iter()
+            .map(|error| {
+                (
+                    error.file.display().to_string(),
+                    String::from("Failed to compile"),
+                    error.diagnostics.clone(),
+                )
+            })
+            .collect::<Vec<_>>(),
+    )
+}
+
+fn template_file(hb: &Handlebars<'_>, name: &str) -> Option<PathBuf> {
+    let index = hb
+        .render(TEMPLATE_FILES_TEMPLATE, &())
+        .ok()?
+        .parse::<usize>()
+        .ok()?;
+
+    TEMPLATE_FILES.read().ok().and_then(|templates| {
+        templates
+            .get(index)?
+            .iter()
+            .find(|(template, _)| template == name)
+            .map(|(_, path)| path.clone())
+    })
+}
+
+fn parse_template_file(hb: &Handlebars<'_>, name: &str) -> Option<TemplateError> {
+    let source = fs::read_to_string(template_file(hb, name)?).ok()?;
+    Template::compile(&source).err()
+}
+
+/// Lines surrounding `line` of the template file, marking the failing position
+fn template_snippet(hb: &Handlebars<'_>, name: &str, line: usize, column: usize) -> Option<String> {
+    let source = fs::read_to_string(template_file(hb, name)?).ok()?;
+    let mut snippet = String::new();
+
+    for (i, content) in source.lines().enumerate() {
+        let number = i + 1;
+        if number + 3 < line || number > line + 3 {
+            continue;
+        }
+
+        snippet += &format!("{number:4} | {content}\n");
+        if number == line {
+            snippet += &format!("     | {}^\n", " ".repeat(column.saturating_sub(1)));
+        }
+    }
+
+    Some(snippet)
+}
+
+/// Renders each `(location, message, source)` entry as a section of the overlay
+fn render_overlay(title: &str, entries: &[(String, String, String)]) -> Response {
+    let sections = entries
+        .iter()
+        .map(|(location, message, source)| {
+            format!(
+                "<h2>{}</h2><p>{}</p><pre>{}</pre>",
+                html_escape(location),
+                html_escape(message),
+                html_escape(source)
+            )
+        })
+        .collect::<String>();
+
+    let html = warp::reply::html(format!(
+        r#"
+            <!DOCTYPE html>
+            <html>
+                <head>
+                    <title>{title}</title>
+                    <style>
+                        body {{ margin: 0; background: #1e1e1e; color: #eee; font-family: monospace; }}
+                        section {{ padding: 2rem; }}
+                        h1 {{ color: #ff6b6b; }}
+                        pre {{ background: #111; padding: 1rem; overflow-x: auto; }}
+                    </style>
+                </head>
+                <body>
+                    <section>
+                        <h1>{title}</h1>
+                        {sections}
+                    </section>
+                </body>
+            </html>
+        "#
+    ));
+
+    warp::reply::with_status(html, StatusCode::INTERNAL_SERVER_ERROR).into_response()
+}
@@ -5,7 +5,15 @@ use minify_html::{minify, Cfg};
 use serde::Serialize;
 use serde_json::{json, Value};
 
-use crate::{storage::Storage, utility::merge};
+use warp::Reply;
+
+use crate::{
+    overlay::{script_errors, script_errors_response, template_error_response},
+    routing::{has_static_file, CONTENT_TEMPLATE},
+    storage::Storage,
+    utility::merge,
+    RequestState,
+};
 
 #[derive(Clone, PartialEq, PartialOrd)]
 pub enum PageValueScope {
@@ -60,8 +68,10 @@ pub struct Page<T: Serialize> {
     template: Option<String>,
     parent: Option<String>,
     child: Option<String>,
+    layouts: bool,
 
     navigation: Option<Vec<(String, String)>>,
+    request: Option<Value>,
 
     local_style_tree: Vec<String>,
     global_style_tree: Vec<String>,
@@ -82,7 +92,9 @@ where
             template: Some(template.to_string()),
             parent: None,
             child: None,
+            layouts: true,
             navigation: None,
+            request: None,
             local_style_tree: vec![],
             global_style_tree: vec![],
             local_script_tree: vec![],
@@ -98,7 +110,9 @@ where
             template: Some(storage.get_template()),
             parent: Some(storage.get_static_template("base")),
             child: None,
+            layouts: true,
             navigation: Some(storage.construct_navigation()),
+            request: None,
             local_style_tree: vec![String::from("index.css")],
             global_style_tree: vec![String::from("index.css")],
             local_script_tree: vec![String::from("index.js")],
@@ -139,6 +153,14 @@ where
         self
     }
 
+    /**
+     * Renders the page without the `_layout.hbs` files of its directory chain
+     */
+    pub fn without_layouts(mut self) -> Self {
+        self.layouts = false;
+        self
+    }
+
     pub fn with_template(mut self, template_path: &str) -> Self {
         self.template = Some(template_path.to_string());
         self
@@ -159,6 +181,14 @@ where
         self
     }
 
+    /**
+     * Exposes the values of the request state to the templates as `REQUEST`
+     */
+    pub fn with_request_state(mut self, state: &RequestState) -> Self {
+        self.request = Some(state.values());
+        self
+    }
+
     pub fn generate_import_tree(&self, storage: &Storage) -> Value {
         let mut local_styles = self
             .local_style_tree
@@ -204,6 +234,22 @@ where
             None => {}
         }
 
+        if !storage.get_params().is_empty() {
+            export["PARAMS"] = storage
+                .get_params()
+                .iter()
+                .map(|(k, v)| (k.clone(), json!(v)))
+                .collect::<serde_json::Map<String, Value>>()
+                .into();
+        }
+
+        match &self.request {
+            Some(request) => {
+                export["REQUEST"] = request.clone();
+            }
+            None => {}
+        }
+
         match &self.parent {
             Some(parent) => {
                 export["PARENT"] = json!(*parent);
@@ -258,7 +304,24 @@ where
         storage: &Storage,
         template_name: &str,
     ) -> impl warp::Reply {
-        let tree = self.generate_import_tree(storage);
+        if hb.dev_mode() {
+            let errors = script_errors();
+            if !errors.is_empty() {
+                return script_errors_response(&errors);
+            }
+        }
+
+        let layouts = match self.layouts {
+            true => storage
+                .get_layout_templates()
+                .into_iter()
+                .filter(|layout| hb.has_template(layout))
+                .collect(),
+            false => vec![],
+        };
+
+        let mut tree = self.generate_import_tree(storage);
+        inject_layout_imports(&mut tree, hb, storage, &layouts);
 
         let server_value_tree = self
             .values
@@ -277,11 +340,98 @@ where
             None => template_name.to_string(),
         };
 
-        let render = hb
-            .render(&template_name, &server_value_tree)
-            .unwrap_or_else(|err| err.to_string());
+        let render = match hb.render(&template_name, &server_value_tree) {
+            Ok(render) => render,
+            Err(err) => return template_error_response(err, hb),
+        };
+
+        if layouts.is_empty() {
+            let minified = minify(render.as_bytes(), &self._cfg);
+            return warp::reply::html(minified).into_response();
+        }
+
+        // Layouts are rendered from the closest to the root around a placeholder, then placed
+        // around the page's content inside the document rendered through the parent template
+        let mut shell = String::from(LAYOUT_PLACEHOLDER);
+        for layout in layouts.iter().rev() {
+            let mut layout_value_tree = server_value_tree.clone();
+            layout_value_tree["LAYOUT_CONTENT"] = Value::String(shell);
+
+            shell = match hb.render(layout, &layout_value_tree) {
+                Ok(render) => render,
+                Err(err) => return template_error_response(err, hb),
+            };
+        }
+
+        let content = match server_value_tree.get("PARENT").is_some() {
+            true => {
+                let mut content_value_tree = server_value_tree.clone();
+                content_value_tree["PARENT"] = json!(CONTENT_TEMPLATE);
+                hb.render(&template_name, &content_value_tree).ok()
+            }
+            false => None,
+        };
+
+        let render = wrap_layouts(render, content.as_deref(), &shell);
 
         let minified = minify(render.as_bytes(), &self._cfg);
-        warp::reply::html(minified)
+        warp::reply::html(minified).into_response()
+    }
+}
+
+const LAYOUT_PLACEHOLDER: &str = "<!--potion:layout-content-->";
+
+/**
+ * Places the rendered layouts around the page's `content` partial within the document. The
+ * content is rendered on its own through `CONTENT_TEMPLATE` and found in the document, the
+ * layouts wrap the whole document when it isn't, e.g. for pages without a parent
+ */
+fn wrap_layouts(document: String, content: Option<&str>, shell: &str) -> String {
+    let (open, close) = shell.split_once(LAYOUT_PLACEHOLDER).unwrap_or((shell, ""));
+
+    let content = content
+        .filter(|content| !content.trim().is_empty())
+        .and_then(|content| {
+            document
+                .find(content)
+                .map(|start| (start, start + content.len()))
+        });
+
+    match content {
+        Some((start, end)) => format!(
+            "{}{}{}{}{}",
+            &document[..start],
+            open,
+            &document[start..end],
+            close,
+            &document[end..]
+        ),
+        None => format!("{}{}{}", open, document, close),
+    }
+}
+
+/**
+ * Links `_layout.css` and `_layout.js` of each layout before the page's own imports, the same
+ * way `Page::default` links `index.css` and `index.js`. Only files linked by the static router
+ * are imported
+ */
+fn inject_layout_imports(
+    tree: &mut Value,
+    hb: &Handlebars<'static>,
+    storage: &Storage,
+    layouts: &[String],
+) {
+    for (key, extension) in [("STYLE_IMPORTS", "css"), ("SCRIPT_IMPORTS", "js")] {
+        let mut imports: Vec<Value> = layouts
+            .iter()
+            .map(|layout| format!("{}.{}", layout, extension))
+            .filter(|file| has_static_file(hb, file))
+            .map(|file| json!(format!("{}/static/{}", storage.get_mount_path(), file)))
+            .collect();
+
+        if let Some(Value::Array(page_imports)) = tree.get_mut(key) {
+            imports.append(page_imports);
+            *page_imports = imports;
+        }
     }
 }
@@ -0,0 +1,243 @@
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    cell::RefCell,
+    convert::Infallible,
+    fmt::{self, Display},
+    future::Future,
+    net::SocketAddr,
+    panic::{catch_unwind, AssertUnwindSafe},
+    pin::Pin,
+    sync::Once,
+    task::{Context, Poll},
+};
+
+use warp::{
+    filters::BoxedFilter,
+    http::{Request, StatusCode},
+    hyper::{service::Service, Body},
+    reject::Rejection,
+    reply::Response,
+    Filter, Reply,
+};
+
+use crate::{
+    context::RemoteAddr, error::with_vary_accept, prefers_json, recovery::RequestDetails,
+    HtmlError, RequestState,
+};
+
+thread_local! {
+    static BACKTRACE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+static BACKTRACE_HOOK: Once = Once::new();
+
+/**
+ * Panic caught by `potion::catch_panic`, attached as the source of the `500` error
+ */
+#[derive(Debug, Clone)]
+pub struct Panic {
+    pub message: String,
+    /// Captured in dev mode only
+    pub backtrace: Option<String>,
+}
+
+impl Display for Panic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "panicked: {}", self.message)
+    }
+}
+
+impl std::error::Error for Panic {}
+
+/**
+   ### Panic recovery
+   Serves `filter` as a hyper service, turning panics in its handlers into `500` errors instead
+   of dropping the connection. The panic is logged with the route, reported like the errors of
+   `potion::recover` and answered with the standard `500` page, or `application/problem+json`
+   for clients preferring JSON. In dev mode HTML clients see the panic message and backtrace
+   instead.
+
+   The result is a hyper `Service`, not a filter: it can't be passed to `warp::serve`, serve it
+   with hyper directly as below, or use `potion::App` which does so. Panics are caught around
+   the service future, so the request reaches `filter` untouched. It gives the request a
+   `potion::RequestState`. `warp::addr::remote` is only set by `warp::serve`, read the address
+   with `potion::remote_addr()` instead, which is set when the service is created with
+   `with_remote_addr`.
+
+   ### Example usage
+   ```ignore
+       let service = potion::catch_panic(potion::recover(router(context).or(static_router)), true);
+       let make_service = hyper::service::make_service_fn(move |conn: &AddrStream| {
+           let service = service.with_remote_addr(conn.remote_addr());
+           async move { Ok::<_, Infallible>(service) }
+       });
+
+       hyper::Server::bind(&([0, 0, 0, 0], 3030).into()).serve(make_service).await?;
+   ```
+*/
+pub fn catch_panic<F, R>(filter: F, dev: bool) -> CatchPanic
+where
+    F: Filter<Extract = (R,)> + Clone + Send + Sync + 'static,
+    F::Error: Into<Rejection>,
+    R: Reply + 'static,
+{
+    if dev {
+        install_backtrace_hook();
+    }
+
+    CatchPanic {
+        filter: filter.map(Reply::into_response).boxed(),
+        dev,
+        remote: None,
+    }
+}
+
+/**
+ * Service returned by `potion::catch_panic`
+ */
+#[derive(Clone)]
+pub struct CatchPanic {
+    filter: BoxedFilter<(Response,)>,
+    dev: bool,
+    remote: Option<SocketAddr>,
+}
+
+impl CatchPanic {
+    /**
+     * Service for the requests of a connection from `remote`, read with `potion::remote_addr()`
+     */
+    pub fn with_remote_addr(&self, remote: SocketAddr) -> Self {
+        Self {
+            filter: self.filter.clone(),
+            dev: self.dev,
+            remote: Some(remote),
+        }
+    }
+}
+
+impl Service<Request<Body>> for CatchPanic {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, mut request: Request<Body>) -> Self::Future {
+        let details = RequestDetails {
+            method: request.method().clone(),
+            path: request.uri().path().to_string(),
+            headers: request.headers().clone(),
+        };
+
+        if request.extensions().get::<RequestState>().is_none() {
+            request.extensions_mut().insert(RequestState::default());
+        }
+
+        if let Some(remote) = self.remote {
+            request.extensions_mut().insert(RemoteAddr(remote));
+        }
+
+        let future = CatchUnwind::new(warp::service(self.filter.clone()).call(request));
+        let dev = self.dev;
+
+        Box::pin(async move {
+            let panic = match future.await {
+                Ok(result) => return result,
+                Err(panic) => panic,
+            };
+
+            log::error!(
+                "Panic in {} {}: {}",
+                details.method,
+                details.path,
+                panic.message
+            );
+
+            let error = HtmlError::InternalServerError
+                .default()
+                .with_source(panic.clone());
+
+            Ok(details.respond(error, |path, accept, error| {
+                match dev && !accept.is_some_and(prefers_json) {
+                    true => with_vary_accept(render_panic(&panic)),
+                    false => error.negotiate(accept, path),
+                }
+            }))
+        })
+    }
+}
+
+/// Polls the future, catching panics raised while polling
+struct CatchUnwind<F>(Pin<Box<F>>);
+
+impl<F: Future> CatchUnwind<F> {
+    fn new(future: F) -> Self {
+        Self(Box::pin(future))
+    }
+}
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = Result<F::Output, Panic>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match catch_unwind(AssertUnwindSafe(|| self.0.as_mut().poll(cx))) {
+            Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(payload) => Poll::Ready(Err(Panic {
+                message: panic_message(payload),
+                backtrace: BACKTRACE.with(|backtrace| backtrace.borrow_mut().take()),
+            })),
+        }
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => String::from("Unknown panic"),
+        },
+    }
+}
+
+/// Stores the backtrace of each panic for `CatchUnwind`, keeping the previous hook
+fn install_backtrace_hook() {
+    BACKTRACE_HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+
+        std::panic::set_hook(Box::new(move |info| {
+            let backtrace = Backtrace::force_capture().to_string();
+            BACKTRACE.with(|current| *current.borrow_mut() = Some(backtrace));
+            previous(info);
+        }));
+    });
+}
+
+fn render_panic(panic: &Panic) -> Response {
+    let message = handlebars::html_escape(&panic.message);
+    let backtrace = handlebars::html_escape(panic.backtrace.as_deref().unwrap_or_default());
+
+    let html = warp::reply::html(format!(
+        r#"
+            <!DOCTYPE html>
+            <html>
+                <head>
+                    <title>Error - 500</title>
+                </head>
+                <body>
+                    <section class="content">
+                        <h1>500</h1>
+                        <p>Panicked: {message}</p>
+                        <pre>{backtrace}</pre>
+                    </section>
+                </body>
+            </html>
+        "#
+    ));
+
+    warp::reply::with_status(html, StatusCode::INTERNAL_SERVER_ERROR).into_response()
+}
@@ -0,0 +1,316 @@
+use std::{convert::Infallible, sync::Arc};
+
+use handlebars::Handlebars;
+use serde_json::json;
+use warp::{
+    filters::path::FullPath,
+    http::{header::ACCEPT, HeaderMap, HeaderValue, Method},
+    reject::Rejection,
+    reply::Response,
+    Filter, Reply,
+};
+
+use crate::{
+    error::with_vary_accept,
+    prefers_json,
+    report::{report_error, request_id, UnreportedError, REQUEST_ID_HEADER},
+    storage::{resolve_segment, Segment},
+    Error,
+};
+
+/**
+   ### Error pages rendered from the routing tree
+   Renders the closest `_404.hbs` or `_error.hbs` to the failing path with the values
+   `CODE`, `INFO`, `DESCRIPTION` and `PATH`. Not found errors prefer `_404.hbs` over `_error.hbs`.
+   When no template exists the built-in error page is rendered instead.
+
+   ### Example usage
+   ```ignore
+       let routes = potion::recover_with(router(context), ErrorPages::new(hb.clone()));
+   ```
+*/
+#[derive(Clone)]
+pub struct ErrorPages {
+    hb: Arc<Handlebars<'static>>,
+    mount: Vec<String>,
+}
+
+impl ErrorPages {
+    pub fn new(hb: Arc<Handlebars<'static>>) -> Self {
+        Self { hb, mount: vec![] }
+    }
+
+    /**
+     * Sets the base path the routing tree is mounted under
+     */
+    pub fn with_mount(mut self, mount: &[&str]) -> Self {
+        self.mount = Vec::from_iter(mount.iter().map(|s| s.to_string()));
+        self
+    }
+
+    /// Number of segments of the base path the routing tree is mounted under
+    pub(crate) fn mount_depth(&self) -> usize {
+        self.mount.len()
+    }
+
+    /**
+     * Finds the template closest to `path` for the given error code
+     */
+    pub fn find_template(&self, path: &str, code: i16) -> Option<String> {
+        let names: &[&str] = match code {
+            404 => &["_404", "_error"],
+            _ => &["_error"],
+        };
+
+        names
+            .iter()
+            .find_map(|name| self.find_closest_template(path, name))
+    }
+
+    fn find_closest_template(&self, path: &str, name: &str) -> Option<String> {
+        let values: Vec<&str> = path.split("/").filter(|s| !s.is_empty()).collect();
+
+        match values
+            .iter()
+            .zip(self.mount.iter())
+            .all(|(value, mount)| value == mount)
+        {
+            true if values.len() >= self.mount.len() => {}
+            _ => return None,
+        }
+
+        let values = &values[self.mount.len()..];
+        let suffix = format!("::{name}");
+
+        self.hb
+            .get_templates()
+            .keys()
+            .filter_map(|template| {
+                let dir = template.strip_suffix(&suffix)?;
+                let dir = dir.strip_prefix("routing")?;
+                let segments: Vec<&str> = dir.split("::").filter(|s| !s.is_empty()).collect();
+
+                match matches_path(&segments, values) {
+                    true => Some((segments.len(), template)),
+                    false => None,
+                }
+            })
+            .max()
+            .map(|(_, template)| template.clone())
+    }
+
+    /**
+     * Renders the closest error template, falling back to the built-in error page
+     */
+    pub fn render(&self, path: &str, accept: Option<&str>, error: Error) -> Response {
+        if error.redirect.is_some() || accept.is_some_and(prefers_json) {
+            return error.negotiate(accept, path);
+        }
+
+        let Some(template) = self.find_template(path, error.code) else {
+            return error.negotiate(accept, path);
+        };
+
+        let render = self.hb.render(
+            &template,
+            &json!({
+                "CODE": error.code,
+                "INFO": error.info,
+                "DESCRIPTION": error.description(),
+                "PATH": path,
+            }),
+        );
+
+        match render {
+            Ok(html) => {
+                let response =
+                    warp::reply::with_status(warp::reply::html(html), error.status_code());
+                with_vary_accept(error.append_headers(response.into_response()))
+            }
+            Err(err) => {
+                log::error!("Failed to render error template {}: {}", template, err);
+                error.negotiate(accept, path)
+            }
+        }
+    }
+
+    /**
+     * Renders the rejection as `potion::Error`
+     */
+    pub fn recover(&self, path: &str, accept: Option<&str>, rejection: Rejection) -> Response {
+        self.render(path, accept, Error::from(&rejection))
+    }
+}
+
+/// Matches the directory of a template against the requested path. Dynamic segments match
+/// any value and catch-all segments the rest of the path.
+fn matches_path(segments: &[&str], values: &[&str]) -> bool {
+    for (i, segment) in segments.iter().enumerate() {
+        match resolve_segment(segment) {
+            Segment::Static(name) => {
+                if values.get(i) != Some(&name) {
+                    return false;
+                }
+            }
+            Segment::Param(_) => {
+                if values.get(i).is_none() {
+                    return false;
+                }
+            }
+            Segment::CatchAll(_) => return values.len() > i,
+            Segment::OptionalCatchAll(_) => return true,
+        }
+    }
+
+    true
+}
+
+/**
+ * Wraps `filter`, rendering its rejections with the closest error template
+ */
+pub fn recover_with<F, R>(
+    filter: F,
+    pages: ErrorPages,
+) -> impl Filter<Extract = (Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    recover_using(filter, move |path, accept, error| {
+        pages.render(path, accept, error)
+    })
+}
+
+/**
+   ### Rejection recovery
+   Wraps `filter`, turning its rejections into responses with the correct status code.
+   `potion::Error` rejections are rendered as is, warp's built-in rejections (method not allowed,
+   payload too large, missing header...) are converted with `potion::Error::from`.
+   Clients preferring JSON in their `Accept` header receive `application/problem+json` instead
+   of the error page
+
+   ### Example usage
+   ```ignore
+       let routes = potion::recover(router(context).or(static_router));
+       warp::serve(routes).run(([0, 0, 0, 0], 3030)).await;
+   ```
+*/
+pub fn recover<F, R>(filter: F) -> impl Filter<Extract = (Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    recover_using(filter, |path, accept, error| error.negotiate(accept, path))
+}
+
+/// Wraps `filter`, rendering its rejections with `render`
+pub(crate) fn recover_using<F, R, H>(
+    filter: F,
+    render: H,
+) -> impl Filter<Extract = (Response,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+    H: Fn(&str, Option<&str>, Error) -> Response + Clone + Send + Sync + 'static,
+{
+    request_details().and(capture_rejection(filter)).map(
+        move |request: RequestDetails, result: Result<Response, Rejection>| match result {
+            Ok(response) => request.report(response, &render),
+            Err(rejection) => request.respond(Error::from(&rejection), &render),
+        },
+    )
+}
+
+/// Request details used to render and report rejections
+pub(crate) struct RequestDetails {
+    pub(crate) method: Method,
+    pub(crate) path: String,
+    pub(crate) headers: HeaderMap,
+}
+
+impl RequestDetails {
+    /// Reports server errors and renders `error` with `render`, tagging server errors with
+    /// their request id
+    pub(crate) fn respond(
+        &self,
+        error: Error,
+        render: impl FnOnce(&str, Option<&str>, Error) -> Response,
+    ) -> Response {
+        let accept = self
+            .headers
+            .get(ACCEPT)
+            .and_then(|value| value.to_str().ok());
+
+        if error.code < 500 {
+            return render(&self.path, accept, error);
+        }
+
+        let request_id = request_id(&self.headers);
+        report_error(&error, &self.method, &self.path, &request_id);
+
+        let response = render(&self.path, accept, error);
+        with_request_id(response, &request_id)
+    }
+
+    /// Reports and renders the server errors replied outside of a rejection, e.g. by
+    /// `potion::Redirect` or a page failing to render
+    fn report(
+        &self,
+        mut response: Response,
+        render: impl FnOnce(&str, Option<&str>, Error) -> Response,
+    ) -> Response {
+        let Some(UnreportedError(error)) = response.extensions_mut().remove::<UnreportedError>()
+        else {
+            return response;
+        };
+
+        self.respond(error, render)
+    }
+}
+
+fn with_request_id(mut response: Response, request_id: &str) -> Response {
+    if let Ok(value) = HeaderValue::from_str(request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+fn request_details() -> impl Filter<Extract = (RequestDetails,), Error = Infallible> + Clone {
+    warp::method()
+        .and(warp::path::full())
+        .and(warp::header::headers_cloned())
+        .map(|method, path: FullPath, headers| RequestDetails {
+            method,
+            path: path.as_str().to_string(),
+            headers,
+        })
+}
+
+/// Extracts the reply or the rejection of `filter`, so it can be handled with request details
+/// extracted before it.
+fn capture_rejection<F, R>(
+    filter: F,
+) -> impl Filter<Extract = (Result<Response, Rejection>,), Error = Infallible> + Clone
+where
+    F: Filter<Extract = (R,), Error = Rejection> + Clone + Send + Sync + 'static,
+    R: Reply,
+{
+    filter
+        .map(|reply: R| Ok::<Response, Rejection>(reply.into_response()))
+        .recover(|rejection: Rejection| async move { Ok::<_, Infallible>(Err(rejection)) })
+        .unify()
+}
+
+#[test]
+fn test_matches_path() {
+    assert!(matches_path(&[], &["users", "42"]));
+    assert!(matches_path(&["users"], &["users", "42"]));
+    assert!(matches_path(&["users", "[id]"], &["users", "42"]));
+    assert!(matches_path(&["docs", "[...rest]"], &["docs", "a", "b"]));
+    assert!(matches_path(&["docs", "[[...rest]]"], &["docs"]));
+
+    assert!(!matches_path(&["items"], &["users", "42"]));
+    assert!(!matches_path(&["users", "[id]"], &["users"]));
+    assert!(!matches_path(&["docs", "[...rest]"], &["docs"]));
+    assert!(!matches_path(&["users", "new"], &["users", "42"]));
+}
@@ -0,0 +1,214 @@
+use std::convert::Infallible;
+
+use warp::{
+    http::{header, HeaderValue, StatusCode, Uri},
+    reject::Rejection,
+    reply::Response,
+    Filter, Reply,
+};
+
+use crate::{report::UnreportedError, HtmlError};
+
+/// Cookie carrying the flash message of a redirect to the next page
+pub const FLASH_COOKIE: &str = "potion_flash";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RedirectKind {
+    /// `303`, the next request is always `GET`. Used after handling forms
+    SeeOther,
+    /// `307`, the method and body are kept
+    Temporary,
+    /// `308`, the method and body are kept and the redirect may be cached
+    Permanent,
+}
+
+impl RedirectKind {
+    pub fn status_code(self) -> StatusCode {
+        match self {
+            Self::SeeOther => StatusCode::SEE_OTHER,
+            Self::Temporary => StatusCode::TEMPORARY_REDIRECT,
+            Self::Permanent => StatusCode::PERMANENT_REDIRECT,
+        }
+    }
+}
+
+/**
+   ### Redirect response
+   Redirects to a dynamic location, optionally passing a flash message to the next page.
+   Characters not allowed in a URI, such as spaces, are percent-encoded. Locations which still
+   can't be parsed respond with `500`, reported by `potion::recover`, use `Redirect::try_new`
+   to handle them instead
+
+   Can be converted into following
+   - `potion::Error`, to redirect by rejecting
+
+   ### Example usage
+   ```ignore
+       warp::post().map(move || {
+           potion::Redirect::see_other(&format!("/users/{}", id)).with_flash("User created")
+       })
+   ```
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Redirect {
+    pub location: String,
+    pub kind: RedirectKind,
+    pub flash: Option<String>,
+}
+
+impl Redirect {
+    pub fn new(location: &str, kind: RedirectKind) -> Self {
+        Self {
+            location: encode_location(location),
+            kind,
+            flash: None,
+        }
+    }
+
+    /**
+     * Creates a redirect, rejecting with `500` when the location can't be parsed
+     */
+    pub fn try_new(location: &str, kind: RedirectKind) -> Result<Self, Rejection> {
+        let redirect = Self::new(location, kind);
+
+        match redirect.location_header() {
+            Ok(_) => Ok(redirect),
+            Err(err) => Err(warp::reject::custom(
+                HtmlError::InternalServerError.default().with_source(err),
+            )),
+        }
+    }
+
+    pub fn see_other(location: &str) -> Self {
+        Self::new(location, RedirectKind::SeeOther)
+    }
+
+    pub fn temporary(location: &str) -> Self {
+        Self::new(location, RedirectKind::Temporary)
+    }
+
+    pub fn permanent(location: &str) -> Self {
+        Self::new(location, RedirectKind::Permanent)
+    }
+
+    /**
+     * Passes a message to the next page, read with `potion::flash()`
+     */
+    pub fn with_flash(mut self, message: &str) -> Self {
+        self.flash = Some(message.to_string());
+        self
+    }
+}
+
+impl Redirect {
+    fn location_header(&self) -> Result<HeaderValue, warp::http::Error> {
+        let uri = self.location.parse::<Uri>()?;
+        Ok(HeaderValue::from_str(&uri.to_string())?)
+    }
+}
+
+impl Reply for Redirect {
+    fn into_response(self) -> Response {
+        let location = match self.location_header() {
+            Ok(location) => location,
+            Err(err) => {
+                log::error!("Invalid redirect location: {}", self.location);
+
+                let error = HtmlError::InternalServerError.default().with_source(err);
+                let mut response = error.clone().into_response();
+                response.extensions_mut().insert(UnreportedError(error));
+                return response;
+            }
+        };
+
+        let mut response = warp::reply::with_status(warp::reply::reply(), self.kind.status_code())
+            .into_response();
+        let headers = response.headers_mut();
+
+        headers.insert(header::LOCATION, location);
+        headers.insert(
+            header::CACHE_CONTROL,
+            HeaderValue::from_static("no-cache, must-revalidate"),
+        );
+
+        if let Some(flash) = &self.flash {
+            let cookie = format!(
+                "{}={}; Path=/; Max-Age=60; HttpOnly; SameSite=Lax",
+                FLASH_COOKIE,
+                urlencoding::encode(flash)
+            );
+
+            match HeaderValue::from_str(&cookie) {
+                Ok(cookie) => {
+                    headers.append(header::SET_COOKIE, cookie);
+                }
+                Err(err) => log::error!("Failed to set flash message: {}", err),
+            }
+        }
+
+        response
+    }
+}
+
+/// Percent-encodes the characters not allowed in a URI, keeping reserved characters and
+/// existing escapes
+fn encode_location(location: &str) -> String {
+    const ALLOWED: &str = "-._~:/?#[]@!$&'()*+,;=";
+
+    let bytes = location.as_bytes();
+    let mut encoded = String::with_capacity(location.len());
+
+    for (i, byte) in bytes.iter().enumerate() {
+        let escape = *byte == b'%'
+            && bytes.get(i + 1).is_some_and(u8::is_ascii_hexdigit)
+            && bytes.get(i + 2).is_some_and(u8::is_ascii_hexdigit);
+
+        match byte.is_ascii_alphanumeric() || ALLOWED.as_bytes().contains(byte) || escape {
+            true => encoded.push(*byte as char),
+            false => encoded += &format!("%{:02X}", byte),
+        }
+    }
+
+    encoded
+}
+
+/**
+   ### Flash message
+   Extracts the flash message passed by the previous redirect. Clear it with `clear_flash`
+   once shown, so it isn't shown again on reload
+
+   ### Example usage
+   ```ignore
+       warp::get()
+           .and(potion::flash())
+           .map(|flash: Option<String>| potion::clear_flash(render(flash)))
+   ```
+*/
+pub fn flash() -> impl Filter<Extract = (Option<String>,), Error = Infallible> + Clone {
+    warp::header::headers_cloned().map(|headers: warp::http::HeaderMap| {
+        headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .find_map(|cookie| {
+                let (name, value) = cookie.trim().split_once('=')?;
+                match name == FLASH_COOKIE {
+                    true => urlencoding::decode(value).ok().map(|v| v.into_owned()),
+                    false => None,
+                }
+            })
+    })
+}
+
+/**
+ * Expires the flash message cookie with the response
+ */
+pub fn clear_flash(reply: impl Reply) -> Response {
+    let mut response = reply.into_response();
+    response.headers_mut().append(
+        header::SET_COOKIE,
+        HeaderValue::from_static("potion_flash=; Path=/; Max-Age=0; HttpOnly; SameSite=Lax"),
+    );
+    response
+}
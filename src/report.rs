@@ -0,0 +1,145 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use warp::http::{HeaderMap, Method};
+
+use crate::Error;
+
+/// Header the request id is read from and returned in
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+static ERROR_REPORTER: RwLock<Option<Arc<dyn ErrorReporter>>> = RwLock::new(None);
+static REQUEST_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Server error rendered as a reply instead of a rejection, reported and rendered again by
+/// `potion::recover` once the request is known
+pub(crate) struct UnreportedError(pub(crate) Error);
+
+/**
+ * Incident passed to `ErrorReporter::report` for errors with a `5xx` status code
+ */
+#[derive(Debug)]
+pub struct ErrorReport<'a> {
+    pub error: &'a Error,
+    pub method: &'a Method,
+    pub path: &'a str,
+    pub request_id: &'a str,
+    /// Messages of the source errors, from the closest to the root cause
+    pub sources: Vec<String>,
+}
+
+/**
+   ### Sink for server errors
+   Receives every `5xx` error rendered by `potion::recover` and `potion::recover_with`.
+   When no reporter is registered the errors are logged with `log::error!`
+
+   ### Example usage
+   ```ignore
+       struct ChannelReporter(std::sync::mpsc::SyncSender<String>);
+
+       impl potion::ErrorReporter for ChannelReporter {
+           fn report(&self, report: &potion::ErrorReport) {
+               let _ = self.0.try_send(format!("{} {} {}", report.request_id, report.path, report.error));
+           }
+       }
+
+       potion::set_error_reporter(ChannelReporter(sender));
+   ```
+*/
+pub trait ErrorReporter: Send + Sync {
+    fn report(&self, report: &ErrorReport);
+}
+
+/**
+ * Default reporter, logging the error with `log::error!`
+ */
+pub struct LogReporter;
+
+impl ErrorReporter for LogReporter {
+    fn report(&self, report: &ErrorReport) {
+        log::error!(
+            "[{}] {} {} responded {}{}",
+            report.request_id,
+            report.method,
+            report.path,
+            report.error,
+            report
+                .sources
+                .iter()
+                .fold(String::new(), |a, v| a + format!(", caused by: {v}").as_str())
+        );
+    }
+}
+
+/**
+ * Registers the reporter receiving `5xx` errors, replacing the previous one
+ */
+pub fn set_error_reporter(reporter: impl ErrorReporter + 'static) {
+    match ERROR_REPORTER.write() {
+        Ok(mut current) => *current = Some(Arc::new(reporter)),
+        Err(err) => log::error!("Failed to register error reporter: {}", err),
+    }
+}
+
+/**
+ * Passes `error` to the registered reporter when it's a server error
+ */
+pub fn report_error(error: &Error, method: &Method, path: &str, request_id: &str) {
+    if error.code < 500 {
+        return;
+    }
+
+    let mut sources = vec![];
+    let mut source = std::error::Error::source(error);
+    while let Some(err) = source {
+        sources.push(err.to_string());
+        source = err.source();
+    }
+
+    let report = ErrorReport {
+        error,
+        method,
+        path,
+        request_id,
+        sources,
+    };
+
+    let reporter = ERROR_REPORTER
+        .read()
+        .ok()
+        .and_then(|reporter| reporter.clone());
+
+    match reporter {
+        Some(reporter) => reporter.report(&report),
+        None => LogReporter.report(&report),
+    }
+}
+
+/**
+ * Request id sent by the client or proxy in `x-request-id`, otherwise generated
+ */
+pub fn request_id(headers: &HeaderMap) -> String {
+    if let Some(id) = headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
+    {
+        return id.to_string();
+    }
+
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_micros())
+        .unwrap_or_default();
+
+    format!(
+        "{:x}-{:x}",
+        time,
+        REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
@@ -1,14 +1,73 @@
 use std::{
-    fs,
+    fmt, fs,
     fs::DirEntry,
     io,
     path::{Path, PathBuf},
 };
 
 use handlebars::Handlebars;
-use warp::{filters::BoxedFilter, Filter};
+use warp::{
+    filters::{path::FullPath, BoxedFilter},
+    Filter,
+};
+
+use crate::{
+    file_system::{resolve_relative_path, visit_dirs},
+    overlay::register_template_files,
+    HtmlError,
+};
+
+/// Prefix of the empty templates marking the static files linked for a routing tree, e.g.
+/// `potion::static::routing::users::_layout.css`, so each `Handlebars` instance knows its own
+const STATIC_FILE_PREFIX: &str = "potion::static::";
+
+/// Template rendering the `content` partial of a page without its parent document
+pub(crate) const CONTENT_TEMPLATE: &str = "potion::content";
 
-use crate::file_system::{resolve_relative_path, visit_dirs};
+/**
+ * Route as passed to and returned from the `middleware` function of a `_middleware.rs` file
+ *
+ * ### Example usage
+ * ```ignore
+ *     pub fn middleware(router: potion::Router, route: BoxedRoute) -> BoxedRoute {
+ *         warp::header::exact("authorization", "secret")
+ *             .and(route)
+ *             .map(|reply| warp::reply::with_header(reply, "Cache-Control", "no-store"))
+ *             .map(warp::Reply::into_response)
+ *             .boxed()
+ *     }
+ * ```
+ */
+pub type BoxedRoute = BoxedFilter<(warp::reply::Response,)>;
+
+/**
+ * Describes a route linked by `potion::routing!()`, which lists every route in
+ * the generated `ROUTES` in the order they are matched
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouteInfo {
+    /// Path pattern, e.g. `/users/[id]`
+    pub path: &'static str,
+    /// Method of a method file, `None` for routes serving every method
+    pub method: Option<&'static str>,
+    pub file: &'static str,
+    pub module: &'static str,
+    pub template: &'static str,
+    pub params: &'static [&'static str],
+}
+
+impl fmt::Display for RouteInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<8}{} -> {} ({})",
+            self.method.unwrap_or("*"),
+            self.path,
+            self.module,
+            self.file
+        )
+    }
+}
 
 pub fn initialize_routing(
     path: &str,
@@ -17,7 +76,7 @@ pub fn initialize_routing(
     let mut file_map = map_routing_tree(path)?;
 
     #[cfg(feature = "typescript")]
-    typescript_code_gen(&PathBuf::from(path), &mut file_map)?;
+    typescript_code_gen(&PathBuf::from(path), &mut file_map, dev)?;
 
     let static_router = link_static_files(&file_map)
         .or(link_static_dir(path.into()))
@@ -63,17 +122,53 @@ pub fn load_templates(
 
     hb.set_dev_mode(dev);
 
-    for (template, path) in file_map
+    let templates: Vec<(String, PathBuf)> = file_map
         .iter()
         .filter(|(t, _)| t.ends_with(".hbs"))
-        .map(|(t, p)| (t.replace(".hbs", "").replace(".", "::"), p))
-    {
-        hb.register_template_file(&template, path).unwrap();
+        .map(|(t, p)| (t.replace(".hbs", "").replace(".", "::"), p.clone()))
+        .collect();
+
+    for (template, path) in &templates {
+        if let Err(err) = hb.register_template_file(template, path) {
+            // In dev mode templates are reloaded on render, which shows the error in the overlay
+            if !dev {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, err.to_string()));
+            }
+
+            log::error!("{}", err);
+        }
     }
 
+    if dev {
+        register_template_files(&mut hb, templates)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+    }
+
+    register_static_files(&mut hb, file_map)?;
+    hb.register_template_string(CONTENT_TEMPLATE, "{{> content}}")
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+
     Ok(hb)
 }
 
+fn register_static_files(
+    hb: &mut Handlebars<'static>,
+    file_map: &[(String, PathBuf)],
+) -> io::Result<()> {
+    for (route, _) in file_map.iter().filter(|(t, _)| is_static_file(t)) {
+        let name = format!("{}{}", STATIC_FILE_PREFIX, resolve_static_file_name(route));
+
+        hb.register_template_string(&name, "")
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+    }
+
+    Ok(())
+}
+
+fn is_static_file(route: &str) -> bool {
+    route.ends_with(".css") || route.ends_with(".js") || route.ends_with(".js:map")
+}
+
 pub fn link_static_files(l: &Vec<(String, PathBuf)>) -> BoxedFilter<(warp::filters::fs::File,)> {
     let root = warp::path("static")
         .and(warp::path::end())
@@ -82,11 +177,10 @@ pub fn link_static_files(l: &Vec<(String, PathBuf)>) -> BoxedFilter<(warp::filte
 
     let router = l
         .iter()
-        .filter(|(t, _)| t.ends_with(".css") || t.ends_with(".js") || t.ends_with(".js:map"))
+        .filter(|(t, _)| is_static_file(t))
         .map(|(t, p)| (t, p.clone()))
         .fold(root, |f, (r, p)| {
             let name = resolve_static_file_name(r);
-
             let g = warp::path("static")
                 .and(warp::path(name).and(warp::fs::file(p)))
                 .boxed();
@@ -97,6 +191,14 @@ pub fn link_static_files(l: &Vec<(String, PathBuf)>) -> BoxedFilter<(warp::filte
     router
 }
 
+/**
+ * Whether a static file of the given name, e.g. `routing::users::_layout.css`, is linked for the
+ * routing tree the templates were loaded from
+ */
+pub(crate) fn has_static_file(hb: &Handlebars<'_>, name: &str) -> bool {
+    hb.has_template(&format!("{}{}", STATIC_FILE_PREFIX, name))
+}
+
 pub fn resolve_static_file_name(tree: &String) -> String {
     let tree = tree
         .split(".")
@@ -106,6 +208,26 @@ pub fn resolve_static_file_name(tree: &String) -> String {
     format!("{}.{}", route.join("::"), extension.first().unwrap()).replace("js:map", "js.map")
 }
 
+/**
+ * Responds with `405 Method Not Allowed` listing the methods served by the path's method files
+ */
+pub fn method_not_allowed(
+    allow: &'static [&'static str],
+) -> impl Filter<Extract = (warp::reply::Response,), Error = std::convert::Infallible> + Clone {
+    warp::path::full()
+        .and(warp::header::headers_cloned())
+        .map(move |path: FullPath, headers: warp::http::HeaderMap| {
+            let accept = headers
+                .get(warp::http::header::ACCEPT)
+                .and_then(|value| value.to_str().ok());
+
+            HtmlError::MethodNotAllowed
+                .default()
+                .with_allow(allow)
+                .negotiate(accept, path.as_str())
+        })
+}
+
 pub fn link_static_dir(path: PathBuf) -> BoxedFilter<(warp::fs::File,)> {
     warp::path("static")
         .and(warp::fs::dir(path.join(Path::new("static"))))
@@ -116,7 +238,9 @@ pub fn link_static_dir(path: PathBuf) -> BoxedFilter<(warp::fs::File,)> {
 pub fn typescript_code_gen(
     routing_path: &Path,
     file_map: &mut Vec<(String, PathBuf)>,
+    dev: bool,
 ) -> io::Result<()> {
+    use crate::overlay::{register_script_error, ScriptError};
     use crate::wsc::ts_to_js;
     use minify_js::{minify, Session, TopLevelMode};
 
@@ -139,14 +263,37 @@ pub fn typescript_code_gen(
             .join("../dist")
             .join(map_route.replace(".js:map", ".js.map").clone());
 
-        let (out, map) = ts_to_js(filename, &content).expect("Failed to compile ts file");
+        // In dev mode the failure is shown in the overlay instead of stopping the server
+        let (out, map) = match ts_to_js(filename, &content) {
+            Ok(compiled) => compiled,
+            Err(err) => {
+                log::error!("Failed to compile {}: {}", path.display(), err);
+
+                if !dev {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Failed to compile {}: {}", path.display(), err),
+                    ));
+                }
+
+                register_script_error(ScriptError {
+                    file: path.clone(),
+                    diagnostics: err.to_string(),
+                });
+                continue;
+            }
+        };
         let out = out.as_bytes();
         let map = map.as_bytes();
 
         let mut out_buffer = Vec::new();
 
-        minify(&session, TopLevelMode::Global, out, &mut out_buffer)
-            .expect("Failed to minify generated js");
+        minify(&session, TopLevelMode::Global, out, &mut out_buffer).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to minify {}: {:?}", path.display(), err),
+            )
+        })?;
 
         let source_map_ref = format!(
             "\n//# sourceMappingURL=/static/{}",
@@ -1,15 +1,17 @@
 #[derive(Debug, Clone)]
 pub struct Storage {
     path: String,
+    mount: Vec<String>,
+    params: Vec<(String, String)>,
 }
 
 impl Storage {
     pub fn get_static_file(&self, path: &str) -> String {
-        format!("/static/static::{}", path)
+        format!("{}/static/static::{}", self.get_mount_path(), path)
     }
 
     pub fn get_local_file(&self, name: &str) -> String {
-        format!("/static/routing::{}::{}", self.path, name)
+        format!("{}/static/{}", self.get_mount_path(), self.get_local_template(name))
     }
 
     pub fn get_template(&self) -> String {
@@ -17,9 +19,32 @@ impl Storage {
     }
 
     pub fn get_local_template(&self, name: &str) -> String {
+        if self.path.is_empty() {
+            return format!("routing::{}", name);
+        }
+
         format!("routing::{}::{}", self.path, name)
     }
 
+    /**
+     * Names of the `_layout.hbs` templates along the directory chain, ordered from the root
+     */
+    pub fn get_layout_templates(&self) -> Vec<String> {
+        let mut layouts = vec![String::from("routing::_layout")];
+
+        if self.path.is_empty() {
+            return layouts;
+        }
+
+        let segments: Vec<&str> = self.path.split("::").collect();
+
+        for i in 0..segments.len() {
+            layouts.push(format!("routing::{}::_layout", segments[..=i].join("::")));
+        }
+
+        layouts
+    }
+
     pub fn get_static_template(&self, path: &str) -> String {
         format!("static::{}", path)
     }
@@ -28,26 +53,172 @@ impl Storage {
         &self.path
     }
 
+    /**
+     * Base path the routing tree is mounted under, e.g. `/admin`. Empty when not mounted
+     */
+    pub fn get_mount_path(&self) -> String {
+        self.mount
+            .iter()
+            .fold(String::new(), |a, v| a + format!("/{v}").as_str())
+    }
+
+    /**
+     * Returns the resolved value of a dynamic `[name]` segment
+     */
+    pub fn get_param(&self, name: &str) -> Option<&String> {
+        self.params
+            .iter()
+            .find_map(|(k, v)| if k == name { Some(v) } else { None })
+    }
+
+    pub fn get_params(&self) -> &Vec<(String, String)> {
+        &self.params
+    }
+
+    /**
+     * Sets the base path the routing tree is mounted under
+     */
+    pub fn with_mount(mut self, mount: &[&str]) -> Self {
+        self.mount = Vec::from_iter(mount.iter().map(|s| s.to_string()));
+        self
+    }
+
+    /**
+     * Segments from the first dynamic segment on, matched by `Router::with_context`
+     */
+    pub fn get_dynamic_segments(&self) -> Vec<&str> {
+        self.path
+            .split("::")
+            .filter(|s| !s.is_empty())
+            .skip_while(|s| matches!(resolve_segment(s), Segment::Static(_)))
+            .collect()
+    }
+
+    /**
+     * Assigns the values matched by `Router::with_context` to the dynamic segments, in order.
+     * Values are percent-decoded, catch-all values keep their `/` separators
+     */
+    pub fn with_params(&self, values: Vec<String>) -> Self {
+        let decode = |value: &str| {
+            urlencoding::decode(value)
+                .map(|v| v.into_owned())
+                .unwrap_or(value.to_string())
+        };
+
+        let params = self
+            .path
+            .split("::")
+            .filter_map(|segment| match resolve_segment(segment) {
+                Segment::Static(_) => None,
+                Segment::Param(name) => Some((name, false)),
+                Segment::CatchAll(name) | Segment::OptionalCatchAll(name) => Some((name, true)),
+            })
+            .zip(values)
+            .map(|((name, catch_all), value)| {
+                let value = match catch_all {
+                    true => value.split("/").map(decode).collect::<Vec<String>>().join("/"),
+                    false => decode(&value),
+                };
+
+                (name.to_string(), value)
+            })
+            .collect();
+
+        Self {
+            path: self.path.clone(),
+            mount: self.mount.clone(),
+            params,
+        }
+    }
+
+    /**
+     * Links to each directory of the route, dynamic segments being replaced by their values.
+     * Hrefs are percent-encoded while labels keep the decoded value
+     */
     pub fn construct_navigation(&self) -> Vec<(String, String)> {
-        let a: Vec<String> = self.path.split("::").map(|n| n.to_string()).collect();
+        let a: Vec<(String, String)> = self
+            .path
+            .split("::")
+            .map(|n| match resolve_segment(n).param_name().and_then(|p| self.get_param(p)) {
+                Some(value) => (
+                    value
+                        .split("/")
+                        .map(|v| urlencoding::encode(v).into_owned())
+                        .collect::<Vec<String>>()
+                        .join("/"),
+                    value.split("/").last().unwrap_or_default().to_string(),
+                ),
+                None => (n.to_string(), n.to_string()),
+            })
+            .filter(|(n, _)| !n.is_empty())
+            .collect();
         let mut b: Vec<(String, String)> = vec![];
 
         for i in 0..a.len() {
             let c = a
                 .iter()
                 .take(i + 1)
-                .fold(String::new(), |a, v| a + format!("/{v}").as_str());
+                .fold(String::new(), |a, (v, _)| a + format!("/{v}").as_str());
 
-            let last = &c.split("/").last().map(|s| s.to_string()).unwrap();
-            b.push((c, last.to_owned()));
+            b.push((format!("{}{}", self.get_mount_path(), c), a[i].1.clone()));
         }
 
         b
     }
 }
 
+pub enum Segment<'a> {
+    Static(&'a str),
+    /// `[name]`
+    Param(&'a str),
+    /// `[...name]`
+    CatchAll(&'a str),
+    /// `[[...name]]`
+    OptionalCatchAll(&'a str),
+}
+
+impl Segment<'_> {
+    pub fn param_name(&self) -> Option<&str> {
+        match self {
+            Segment::Static(_) => None,
+            Segment::Param(name) | Segment::CatchAll(name) | Segment::OptionalCatchAll(name) => {
+                Some(name)
+            }
+        }
+    }
+}
+
+pub fn resolve_segment(segment: &str) -> Segment<'_> {
+    if let Some(name) = segment
+        .strip_prefix("[[...")
+        .and_then(|s| s.strip_suffix("]]"))
+        .filter(|s| !s.is_empty())
+    {
+        return Segment::OptionalCatchAll(name);
+    }
+
+    match segment
+        .strip_prefix('[')
+        .and_then(|s| s.strip_suffix(']'))
+        .filter(|s| !s.is_empty())
+    {
+        Some(name) => match name.strip_prefix("...").filter(|s| !s.is_empty()) {
+            Some(name) => Segment::CatchAll(name),
+            None => Segment::Param(name),
+        },
+        None => Segment::Static(segment),
+    }
+}
+
+/// Method files (`get.rs`, `post.rs`, ...) share the template and local files of their directory
+pub const METHOD_FILES: &[&str] = &["get", "post", "put", "patch", "delete", "head", "options"];
+
 impl From<&[&str]> for Storage {
     fn from(value: &[&str]) -> Self {
+        let value = match value.split_last() {
+            Some((last, rest)) if METHOD_FILES.contains(last) => rest,
+            _ => value,
+        };
         let value = Vec::from_iter(
             value
                 .iter()
@@ -56,6 +227,34 @@ impl From<&[&str]> for Storage {
         );
         Self {
             path: value.join("::"),
+            mount: vec![],
+            params: vec![],
         }
     }
 }
+
+#[test]
+fn test_params() {
+    let storage = Storage::from(&["users", "[id]", "docs", "[...rest]", "index"][..]);
+    let storage = storage.with_params(vec![String::from("a%20b"), String::from("x%2Fy/z")]);
+
+    assert_eq!(storage.get_dynamic_segments(), ["[id]", "docs", "[...rest]"]);
+    assert_eq!(storage.get_param("id").map(String::as_str), Some("a b"));
+    assert_eq!(storage.get_param("rest").map(String::as_str), Some("x/y/z"));
+    assert_eq!(storage.get_param("missing"), None);
+}
+
+#[test]
+fn test_navigation() {
+    let storage = Storage::from(&["users", "[id]", "get"][..])
+        .with_mount(&["admin"])
+        .with_params(vec![String::from("a%20b")]);
+
+    assert_eq!(
+        storage.construct_navigation(),
+        [
+            (String::from("/admin/users"), String::from("users")),
+            (String::from("/admin/users/a%20b"), String::from("a b")),
+        ]
+    );
+}
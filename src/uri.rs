@@ -16,6 +16,24 @@ impl Builder {
         }
     }
 
+    /**
+     * Appends an url-encoded path segment
+     */
+    pub fn path_add(mut self, segment: &str) -> Self {
+        if !self.location.ends_with("/") {
+            self.location += "/";
+        }
+
+        self.location += &urlencoding::encode(segment);
+        self
+    }
+
+    pub fn path_extend(self, segments: &[&str]) -> Self {
+        segments
+            .iter()
+            .fold(self, |builder, segment| builder.path_add(segment))
+    }
+
     pub fn query_add(mut self, key: &str, value: &str) -> Self {
         self.query
             .insert(String::from(key), urlencoding::encode(value).into_owned());
@@ -1,4 +1,9 @@
-use std::{error::Error, io};
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
 
 use swc::{config::{IsModule, SourceMapsConfig}, Compiler, PrintArgs};
 use swc_common::{errors::Handler, source_map::SourceMap, sync::Lrc, Mark, GLOBALS};
@@ -7,7 +12,41 @@ use swc_ecma_parser::Syntax;
 use swc_ecma_transforms_typescript::strip;
 use swc_ecma_visit::FoldWith;
 
+/// Collects the diagnostics emitted by swc, so they can be returned with the error
+#[derive(Clone, Default)]
+struct Diagnostics(Arc<Mutex<Vec<u8>>>);
 
+impl Diagnostics {
+    fn error(&self, err: impl Display) -> Box<dyn Error> {
+        let diagnostics = self
+            .0
+            .lock()
+            .map(|buffer| String::from_utf8_lossy(&buffer).into_owned())
+            .unwrap_or_default();
+
+        match diagnostics.trim().is_empty() {
+            true => err.to_string().into(),
+            false => format!("{}\n{}", err, diagnostics).into(),
+        }
+    }
+}
+
+impl Write for Diagnostics {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.0.lock() {
+            Ok(mut buffer) => buffer.extend_from_slice(buf),
+            Err(_) => return Err(io::Error::new(io::ErrorKind::Other, "Diagnostics poisoned")),
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Compiles TypeScript into JavaScript and its sourcemap. The error contains the swc diagnostics
+/// with the position of the failure
 pub fn ts_to_js(filename: &str, ts_code: &str) -> Result<(String, String), Box<dyn Error>> {
     let cm = Lrc::new(SourceMap::new(swc_common::FilePathMapping::empty()));
     let mut args = PrintArgs::default();
@@ -19,7 +58,9 @@ pub fn ts_to_js(filename: &str, ts_code: &str) -> Result<(String, String), Box<d
         ts_code.to_string(),
     );
 
-    let handler = Handler::with_emitter_writer(Box::new(io::stderr()), Some(compiler.cm.clone()));
+    let diagnostics = Diagnostics::default();
+    let handler =
+        Handler::with_emitter_writer(Box::new(diagnostics.clone()), Some(compiler.cm.clone()));
 
     return GLOBALS.set(&Default::default(), || {
         let program = compiler
@@ -31,7 +72,7 @@ pub fn ts_to_js(filename: &str, ts_code: &str) -> Result<(String, String), Box<d
                 IsModule::Bool(false),
                 Some(compiler.comments()),
             )
-            ?;
+            .map_err(|err| diagnostics.error(err))?;
 
         // Add TypeScript type stripping transform
         let top_level_mark = Mark::new();
@@ -43,7 +84,7 @@ pub fn ts_to_js(filename: &str, ts_code: &str) -> Result<(String, String), Box<d
                 &program,
                 args,
             )
-        ?;
+            .map_err(|err| diagnostics.error(err))?;
 
         return Ok((ret.code, ret.map.expect("no sourcemap")));
     });